@@ -0,0 +1,293 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use crate::intcode::{ErrorKind, Input, InputOutput, Mem, Output};
+
+fn is_ascii(x: Mem) -> bool {
+    (0..128).contains(&x)
+}
+
+/// The text exchanged with a program, input and output interleaved. Clones
+/// share the same text, so one transcript can be given to both an
+/// `AsciiInput` and an `AsciiOutput`.
+#[derive(Clone,Default)]
+pub struct Transcript {
+    text: Rc<RefCell<String>>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Transcript { text: Rc::new(RefCell::new(String::new())) }
+    }
+
+    pub fn text(&self) -> String {
+        self.text.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.text.borrow_mut().clear()
+    }
+
+    fn push_str(&self, s: &str) {
+        self.text.borrow_mut().push_str(s)
+    }
+
+    fn push(&self, c: char) {
+        self.text.borrow_mut().push(c)
+    }
+}
+
+/// Feeds text to a program one character at a time. Text is taken a line at
+/// a time, first from the queued lines and then from the reader, if any; a
+/// missing final newline is added.
+#[derive(Default)]
+pub struct AsciiInput {
+    lines: VecDeque<String>,
+    current: VecDeque<Mem>,
+    reader: Option<Box<dyn BufRead>>,
+    echo: bool,
+    transcript: Option<Transcript>,
+}
+
+impl AsciiInput {
+    pub fn new() -> Self {
+        AsciiInput::default()
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        let mut input = AsciiInput::new();
+        input.push_str(text);
+        input
+    }
+
+    /// Reads further lines from `reader` once the queued ones run out.
+    pub fn with_reader<R: BufRead + 'static>(mut self, reader: R) -> Self {
+        self.reader = Some(Box::new(reader));
+        self
+    }
+
+    /// Prints every line to stdout as the program starts reading it.
+    pub fn with_echo(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    pub fn with_transcript(mut self, transcript: &Transcript) -> Self {
+        self.transcript = Some(transcript.clone());
+        self
+    }
+
+    /// Queues one or more lines.
+    pub fn push_str(&mut self, text: &str) {
+        self.lines.extend(text.lines().map(String::from));
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        self.push_str(line);
+    }
+
+    /// True if neither a queued line nor part of the current one is left.
+    /// The reader is not consulted.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.current.is_empty()
+    }
+
+    fn read_line(&mut self) -> Result<String, ErrorKind> {
+        if let Some(line) = self.lines.pop_front() {
+            return Ok(line);
+        }
+        let reader = self.reader.as_mut().ok_or(ErrorKind::InputExhausted)?;
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => Err(ErrorKind::InputExhausted),
+            Ok(_) => Ok(line.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => Err(ErrorKind::InputFailed(e.to_string())),
+        }
+    }
+
+    fn next_line(&mut self) -> Result<(), ErrorKind> {
+        let mut line = self.read_line()?;
+        if let Some(c) = line.chars().find(|c| !c.is_ascii()) {
+            return Err(ErrorKind::InputFailed(format!("non-ASCII character {:?} in input", c)));
+        }
+        line.push('\n');
+        if self.echo {
+            print!("{}", line);
+        }
+        if let Some(t) = &self.transcript {
+            t.push_str(&line);
+        }
+        self.current.extend(line.bytes().map(Mem::from));
+        Ok(())
+    }
+}
+
+impl Input for AsciiInput {
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+        if self.current.is_empty() {
+            self.next_line()?;
+        }
+        Ok(self.current.pop_front().unwrap())
+    }
+}
+
+/// Collects the text a program prints. Values outside the ASCII range, such
+/// as puzzle answers, are kept apart in `values`.
+#[derive(Default)]
+pub struct AsciiOutput {
+    text: String,
+    line_start: usize,
+    values: Vec<Mem>,
+    echo: bool,
+    transcript: Option<Transcript>,
+}
+
+impl AsciiOutput {
+    pub fn new() -> Self {
+        AsciiOutput::default()
+    }
+
+    /// Prints the text to stdout as it arrives; other values are printed on
+    /// a line of their own.
+    pub fn with_echo(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    /// Text goes to the transcript as is, other values as a line with the
+    /// number.
+    pub fn with_transcript(mut self, transcript: &Transcript) -> Self {
+        self.transcript = Some(transcript.clone());
+        self
+    }
+
+    /// All text printed so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Removes and returns the next complete line that hasn't been read yet,
+    /// without its newline.
+    pub fn read_line(&mut self) -> Option<String> {
+        let len = self.text[self.line_start..].find('\n')?;
+        let line = self.text[self.line_start..self.line_start + len].to_string();
+        self.line_start += len + 1;
+        Some(line)
+    }
+
+    pub fn values(&self) -> &[Mem] {
+        &self.values
+    }
+
+    /// Forgets all text and values.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.line_start = 0;
+        self.values.clear();
+    }
+}
+
+impl Output for AsciiOutput {
+    fn next_output(&mut self, x: Mem) {
+        if is_ascii(x) {
+            let c = x as u8 as char;
+            self.text.push(c);
+            if self.echo {
+                print!("{}", c);
+                if c == '\n' {
+                    let _ = io::stdout().flush();
+                }
+            }
+            if let Some(t) = &self.transcript {
+                t.push(c);
+            }
+        } else {
+            self.values.push(x);
+            if self.echo {
+                println!("{}", x);
+            }
+            if let Some(t) = &self.transcript {
+                t.push_str(&format!("{}\n", x));
+            }
+        }
+    }
+}
+
+/// Both halves of an ASCII session, for functions that take one
+/// `InputOutput`.
+#[derive(Default)]
+pub struct AsciiIO {
+    pub input: AsciiInput,
+    pub output: AsciiOutput,
+}
+
+impl AsciiIO {
+    pub fn new(input: AsciiInput, output: AsciiOutput) -> Self {
+        AsciiIO { input, output }
+    }
+}
+
+impl InputOutput for AsciiIO {
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+        self.input.next_input()
+    }
+
+    fn next_output(&mut self, x: Mem) {
+        self.output.next_output(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::intcode::run_program_splitio;
+
+    // Echoes input until it reads a '.', then prints 1000.
+    const ECHO: &str = "\
+    top:    IN  [c]
+            EQ  [c] #46 [t]
+            JT  [t] #done
+            OUT [c]
+            JT  #1 #top
+    done:   OUT #1000
+            HLT
+    c:      DATA 0
+    t:      DATA 0";
+
+    #[test]
+    fn test_session() {
+        let transcript = Transcript::new();
+        let mut input = AsciiInput::from_text("hello\nworld").with_transcript(&transcript);
+        input.push_line(".");
+        let mut output = AsciiOutput::new().with_transcript(&transcript);
+        run_program_splitio(assemble(ECHO).unwrap(), &mut input, &mut output).unwrap();
+        // The newline after the '.' is never read.
+        assert_eq!(input.next_input(), Ok(10));
+        assert!(input.is_empty());
+        assert_eq!(output.text(), "hello\nworld\n");
+        assert_eq!(output.values(), &[1000]);
+        assert_eq!(output.read_line(), Some(String::from("hello")));
+        assert_eq!(output.read_line(), Some(String::from("world")));
+        assert_eq!(output.read_line(), None);
+        // Output is interleaved with input line by line, as the program echoes
+        // each character right after reading it.
+        assert_eq!(transcript.text(), "hello\nhello\nworld\nworld\n.\n1000\n");
+    }
+
+    #[test]
+    fn test_reader() {
+        let mut input = AsciiInput::from_text("ab").with_reader(&b"c\r\nd"[..]);
+        let read: Result<Vec<Mem>, ErrorKind> = (0..7).map(|_| input.next_input()).collect();
+        assert_eq!(read.unwrap(), "ab\nc\nd\n".bytes().map(Mem::from).collect::<Vec<Mem>>());
+        assert_eq!(input.next_input(), Err(ErrorKind::InputExhausted));
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let mut input = AsciiInput::from_text("caf\u{e9}");
+        assert!(matches!(input.next_input(), Err(ErrorKind::InputFailed(_))));
+        assert_eq!(input.next_input(), Err(ErrorKind::InputExhausted));
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use crate::intcode::{Mem, Op, Param, Ptr};
+
+// Assembler syntax, one statement per line:
+//
+//     loop:   ADD  [counter] #1 [counter]   ; comment
+//             JT   rel-2 #loop
+//             DATA 1, 2, loop
+//
+// Parameters are `[addr]` (position), `#val` (immediate) or `rel+N` (relative).
+// Addresses and values may be numbers, labels or `label+N`. A numeric prefix
+// like `12:` asserts the address of the statement, so disassembler listings
+// assemble back into the original program.
+
+#[derive(Debug,PartialEq,Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+enum Expr {
+    Num(Mem),
+    Label(String, Mem),
+}
+
+enum Operand {
+    Pos(Expr),
+    Imm(Expr),
+    Rel(Mem),
+}
+
+enum Statement {
+    Instr(String, Vec<Operand>),
+    Data(Vec<Expr>),
+}
+
+struct Item {
+    line: usize,
+    addr: Ptr,
+    statement: Statement,
+}
+
+fn arity(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "ADD" | "MUL" | "LT" | "EQ" => Some(3),
+        "JT" | "JF" => Some(2),
+        "IN" | "OUT" | "ARB" => Some(1),
+        "HLT" => Some(0),
+        _ => None,
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_num(s: &str) -> Result<Mem, String> {
+    let s = s.strip_prefix('+').unwrap_or(s);
+    s.parse().map_err(|_| format!("invalid number '{}'", s))
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    if let Ok(val) = parse_num(s) {
+        return Ok(Expr::Num(val));
+    }
+    let (name, offset) = match s.find(['+', '-']) {
+        Some(ix) => (&s[..ix], parse_num(&s[ix..])?),
+        None => (s, 0),
+    };
+    if is_identifier(name) {
+        Ok(Expr::Label(name.to_string(), offset))
+    } else {
+        Err(format!("invalid expression '{}'", s))
+    }
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner.strip_suffix(']').ok_or(format!("unterminated '[' in '{}'", s))?;
+        Ok(Operand::Pos(parse_expr(inner)?))
+    } else if let Some(val) = s.strip_prefix('#') {
+        Ok(Operand::Imm(parse_expr(val)?))
+    } else if let Some(adj) = s.strip_prefix("rel") {
+        if adj.is_empty() {
+            Ok(Operand::Rel(0))
+        } else if adj.starts_with('+') || adj.starts_with('-') {
+            Ok(Operand::Rel(parse_num(adj)?))
+        } else {
+            Err(format!("invalid relative operand '{}'", s))
+        }
+    } else {
+        Err(format!("operand '{}' needs a mode: [addr], #val or rel+N", s))
+    }
+}
+
+fn split_operands(s: &str) -> Vec<&str> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn parse(source: &str) -> Result<(Vec<Item>, HashMap<String, Ptr>), AsmError> {
+    let mut items = Vec::new();
+    let mut labels = HashMap::new();
+    let mut addr: Ptr = 0;
+
+    for (ix, raw_line) in source.lines().enumerate() {
+        let line = ix + 1;
+        let err = |message: String| AsmError { line, message };
+
+        let mut text = match raw_line.find(';') {
+            Some(pos) => &raw_line[..pos],
+            None => raw_line,
+        }.trim();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if let Ok(expected) = label.parse::<Ptr>() {
+                if expected != addr {
+                    return Err(err(format!("address {} asserted, but statement is at {}", expected, addr)));
+                }
+            } else if is_identifier(label) {
+                if labels.insert(label.to_string(), addr).is_some() {
+                    return Err(err(format!("duplicate label '{}'", label)));
+                }
+            } else {
+                return Err(err(format!("invalid label '{}'", label)));
+            }
+            text = text[colon+1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (head, rest) = match text.find(char::is_whitespace) {
+            Some(pos) => (&text[..pos], &text[pos..]),
+            None => (text, ""),
+        };
+        let mnemonic = head.to_ascii_uppercase();
+        let args = split_operands(rest);
+
+        let statement = if mnemonic == "DATA" {
+            let values = args.iter()
+                .map(|a| parse_expr(a))
+                .collect::<Result<Vec<Expr>, String>>()
+                .map_err(err)?;
+            Statement::Data(values)
+        } else {
+            let expected = arity(&mnemonic).ok_or_else(|| err(format!("unknown mnemonic '{}'", head)))?;
+            if args.len() != expected {
+                return Err(err(format!("{} takes {} operands, got {}", mnemonic, expected, args.len())));
+            }
+            let operands = args.iter()
+                .map(|a| parse_operand(a))
+                .collect::<Result<Vec<Operand>, String>>()
+                .map_err(err)?;
+            Statement::Instr(mnemonic, operands)
+        };
+
+        let size = match &statement {
+            Statement::Instr(_, operands) => 1 + operands.len(),
+            Statement::Data(values) => values.len(),
+        };
+        items.push(Item { line, addr, statement });
+        addr += size;
+    }
+    Ok((items, labels))
+}
+
+fn resolve(expr: &Expr, labels: &HashMap<String, Ptr>) -> Result<Mem, String> {
+    match expr {
+        Expr::Num(val) => Ok(*val),
+        Expr::Label(name, offset) => {
+            let addr = labels.get(name).ok_or(format!("undefined label '{}'", name))?;
+            (*addr as Mem).checked_add(*offset).ok_or(format!("'{}{:+}' is out of range", name, offset))
+        },
+    }
+}
+
+fn to_param(operand: &Operand, labels: &HashMap<String, Ptr>) -> Result<Param, String> {
+    match operand {
+        Operand::Pos(expr) => {
+            let addr = resolve(expr, labels)?;
+            if addr < 0 {
+                return Err(format!("negative address {}", addr));
+            }
+            Ok(Param::Pos(addr as Ptr))
+        },
+        Operand::Imm(expr) => Ok(Param::Imm(resolve(expr, labels)?)),
+        Operand::Rel(adj) => Ok(Param::Rel(*adj)),
+    }
+}
+
+fn build_op(mnemonic: &str, mut params: Vec<Param>) -> Op {
+    let mut next = || params.remove(0);
+    match mnemonic {
+        "ADD" => Op::Add(next(), next(), next()),
+        "MUL" => Op::Mul(next(), next(), next()),
+        "IN" => Op::In(next()),
+        "OUT" => Op::Out(next()),
+        "JT" => Op::JumpIfTrue(next(), next()),
+        "JF" => Op::JumpIfFalse(next(), next()),
+        "LT" => Op::LessThan(next(), next(), next()),
+        "EQ" => Op::Equals(next(), next(), next()),
+        "ARB" => Op::AdjustRelBase(next()),
+        "HLT" => Op::End,
+        _ => unreachable!("mnemonic checked while parsing"),
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<Mem>, AsmError> {
+    let (items, labels) = parse(source)?;
+    let mut program = Vec::new();
+
+    for item in items {
+        assert_eq!(item.addr, program.len());
+        let line = item.line;
+        let err = |message: String| AsmError { line, message };
+        match item.statement {
+            Statement::Data(values) => {
+                for v in values {
+                    program.push(resolve(&v, &labels).map_err(err)?);
+                }
+            },
+            Statement::Instr(mnemonic, operands) => {
+                let params = operands.iter()
+                    .map(|o| to_param(o, &labels))
+                    .collect::<Result<Vec<Param>, String>>()
+                    .map_err(err)?;
+                let op = build_op(&mnemonic, params);
+                program.push(op.opcode());
+                for p in op.params() {
+                    program.push(match *p {
+                        Param::Pos(ptr) => ptr as Mem,
+                        Param::Imm(val) | Param::Rel(val) => val,
+                    });
+                }
+            },
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::listing;
+
+    #[test]
+    fn test_assemble() {
+        let source = "\
+        top:
+            ARB #1              ; quine from day 9
+            OUT rel-1
+            ADD [100], #1, [100]
+            EQ  [100] #16 [101]
+            JF  [101] #top
+            HLT";
+        assert_eq!(assemble(source).unwrap(),
+                   vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99]);
+    }
+
+    #[test]
+    fn test_labels_and_data() {
+        let source = "\
+            add [x] [y] [sum]
+            out [sum]
+            hlt
+        x: data 3
+        y: data 4
+        sum: data 0, end+1
+        end:";
+        assert_eq!(assemble(source).unwrap(), vec![1,7,8,9, 4,9, 99, 3, 4, 0, 12]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("ADD #1 #2").unwrap_err().line, 1);
+        assert_eq!(assemble("HLT\nJT #1 #nowhere").unwrap_err(),
+                   AsmError { line: 2, message: String::from("undefined label 'nowhere'") });
+        assert!(assemble("FOO [1]").is_err());
+        assert!(assemble("OUT 5").is_err());
+        assert!(assemble("0: HLT\n2: HLT").is_err());
+        assert_eq!(assemble("HLT\nx: OUT [x+9223372036854775807]").unwrap_err(),
+                   AsmError { line: 2, message: String::from("'x+9223372036854775807' is out of range") });
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8, 22201, 1, 2, 3, 11105];
+        assert_eq!(assemble(&listing(&program)).unwrap(), program);
+
+        for source in &[include_str!("../data/day09.in"), include_str!("../data/day25.in")] {
+            let program = crate::io::parse_intcode_program(&source.to_string());
+            assert_eq!(assemble(&listing(&program)).unwrap(), program);
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::ops::{Add, Mul};
+use crate::intcode::{to_ptr, ErrorKind, IntcodeError, Mem, Ptr, DENSE_LIMIT, FAR_LIMIT};
+
+/// A signed integer of any size.
+#[derive(Debug,Clone,Default,PartialEq,Eq,Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant digit first, without
+    /// leading zeros. Zero has no digits and is never negative.
+    digits: Vec<u32>,
+}
+
+impl From<i64> for BigInt {
+    fn from(x: i64) -> Self {
+        let mut magnitude = x.unsigned_abs();
+        let mut digits = Vec::new();
+        while magnitude > 0 {
+            digits.push(magnitude as u32);
+            magnitude >>= 32;
+        }
+        BigInt { negative: x < 0, digits }
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::with_capacity(std::cmp::max(a.len(), b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..std::cmp::max(a.len(), b.len()) {
+        let sum = carry + *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64;
+        digits.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+    digits
+}
+
+/// `a - b`, where `a` is at least as large as `b`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (diff < 0) as i64;
+        if diff < 0 {
+            diff += 1 << 32;
+        }
+        digits.push(diff as u32);
+    }
+    digits
+}
+
+impl BigInt {
+    fn new(negative: bool, mut digits: Vec<u32>) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt { negative: negative && !digits.is_empty(), digits }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The number of bits in the magnitude; zero has none.
+    pub fn bits(&self) -> usize {
+        self.digits.last().map_or(0, |&d| self.digits.len() * 32 - d.leading_zeros() as usize)
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self.digits.iter().rev().fold(0i128, |acc, &d| (acc << 32) | d as i128);
+        i64::try_from(if self.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    /// The nearest `i64`, for reporting values that don't fit.
+    fn saturating_i64(&self) -> i64 {
+        self.to_i64().unwrap_or(if self.negative { i64::MIN } else { i64::MAX })
+    }
+
+    /// Divides the magnitude by a small number in place, returning the remainder.
+    fn div_rem_small(digits: &mut [u32], divisor: u32) -> u32 {
+        let mut rem = 0u64;
+        for d in digits.iter_mut().rev() {
+            let cur = (rem << 32) | *d as u64;
+            *d = (cur / divisor as u64) as u32;
+            rem = cur % divisor as u64;
+        }
+        rem as u32
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.negative == rhs.negative {
+            return BigInt::new(self.negative, add_magnitude(&self.digits, &rhs.digits));
+        }
+        match cmp_magnitude(&self.digits, &rhs.digits) {
+            Ordering::Less => BigInt::new(rhs.negative, sub_magnitude(&rhs.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_magnitude(&self.digits, &rhs.digits)),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        let mut digits = vec![0u32; self.digits.len() + rhs.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in rhs.digits.iter().enumerate() {
+                let cur = digits[i+j] as u64 + a as u64 * b as u64 + carry;
+                digits[i+j] = cur as u32;
+                carry = cur >> 32;
+            }
+            digits[i + rhs.digits.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != rhs.negative, digits)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.digits, &other.digits),
+            (true, true) => cmp_magnitude(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Peel off nine decimal digits at a time.
+        let mut digits = self.digits.clone();
+        let mut chunks = Vec::new();
+        while digits.iter().any(|&d| d != 0) {
+            chunks.push(BigInt::div_rem_small(&mut digits, 1_000_000_000));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum BigEvent {
+    NeedInput,
+    Output(BigInt),
+    Halted,
+}
+
+fn to_big_ptr(addr: &BigInt) -> Result<Ptr, ErrorKind> {
+    to_ptr(addr.to_i64().ok_or(ErrorKind::Overflow)?)
+}
+
+fn mode(opcode: Mem, index: u32) -> Mem {
+    opcode / 100 / 10i64.pow(index) % 10
+}
+
+/// The default for `BigMachine::with_max_bits`.
+pub const MAX_BITS: usize = 1 << 20;
+
+/// A machine whose memory cells hold integers of any size, so arithmetic
+/// only overflows past a configurable number of bits. Opcodes and addresses
+/// still have to fit in a `Mem`. Much slower than `Machine`; only use it for
+/// programs that need it.
+#[derive(Clone)]
+pub struct BigMachine {
+    cells: Vec<BigInt>,
+    dense_limit: Ptr,
+    far: HashMap<Ptr, BigInt>,
+    ip: Ptr,
+    rel_base: BigInt,
+    input: VecDeque<BigInt>,
+    halted: bool,
+    max_bits: usize,
+}
+
+impl BigMachine {
+    pub fn new(program: Vec<Mem>) -> Self {
+        BigMachine {
+            dense_limit: std::cmp::max(DENSE_LIMIT, program.len()),
+            cells: program.into_iter().map(BigInt::from).collect(),
+            far: HashMap::new(),
+            ip: 0,
+            rel_base: BigInt::default(),
+            input: VecDeque::new(),
+            halted: false,
+            max_bits: MAX_BITS,
+        }
+    }
+
+    /// Makes arithmetic whose result needs more than `max_bits` bits fail
+    /// with `Overflow`.
+    pub fn with_max_bits(self, max_bits: usize) -> Self {
+        BigMachine { max_bits, ..self }
+    }
+
+    pub fn ip(&self) -> Ptr {
+        self.ip
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn read(&self, ptr: Ptr) -> BigInt {
+        if ptr < self.cells.len() {
+            self.cells[ptr].clone()
+        } else if ptr < self.dense_limit {
+            BigInt::default()
+        } else {
+            self.far.get(&ptr).cloned().unwrap_or_default()
+        }
+    }
+
+    pub fn poke(&mut self, ptr: Ptr, val: BigInt) {
+        if ptr >= self.dense_limit {
+            self.far.insert(ptr, val);
+            return;
+        }
+        if ptr >= self.cells.len() {
+            self.cells.resize(ptr + 1, BigInt::default());
+        }
+        self.cells[ptr] = val;
+    }
+
+    pub fn push_input(&mut self, x: BigInt) {
+        self.input.push_back(x)
+    }
+
+    pub fn resume(&mut self) -> Result<BigEvent, IntcodeError> {
+        loop {
+            if let Some(event) = self.step()? {
+                return Ok(event);
+            }
+        }
+    }
+
+    pub fn step(&mut self) -> Result<Option<BigEvent>, IntcodeError> {
+        self.execute().map_err(|kind| IntcodeError {
+            kind,
+            ip: self.ip,
+            opcode: self.read(self.ip).saturating_i64(),
+            rel_base: self.rel_base.saturating_i64(),
+        })
+    }
+
+    fn param_addr(&self, opcode: Mem, index: u32) -> Result<Ptr, ErrorKind> {
+        let raw = self.read(self.ip + 1 + index as Ptr);
+        match mode(opcode, index) {
+            0 => to_big_ptr(&raw),
+            1 => Err(ErrorKind::WriteToImmediate),
+            2 => to_big_ptr(&(&self.rel_base + &raw)),
+            m => Err(ErrorKind::InvalidParameterMode(m)),
+        }
+    }
+
+    fn load(&self, opcode: Mem, index: u32) -> Result<BigInt, ErrorKind> {
+        if mode(opcode, index) == 1 {
+            Ok(self.read(self.ip + 1 + index as Ptr))
+        } else {
+            Ok(self.read(self.param_addr(opcode, index)?))
+        }
+    }
+
+    fn store(&mut self, opcode: Mem, index: u32, val: BigInt) -> Result<(), ErrorKind> {
+        let addr = self.param_addr(opcode, index)?;
+        if addr >= self.dense_limit && self.far.len() >= FAR_LIMIT && !self.far.contains_key(&addr) {
+            return Err(ErrorKind::MemoryLimit);
+        }
+        self.poke(addr, val);
+        Ok(())
+    }
+
+    fn limit(&self, val: BigInt) -> Result<BigInt, ErrorKind> {
+        if val.bits() > self.max_bits {
+            return Err(ErrorKind::Overflow);
+        }
+        Ok(val)
+    }
+
+    fn execute(&mut self) -> Result<Option<BigEvent>, ErrorKind> {
+        if self.halted {
+            return Ok(Some(BigEvent::Halted));
+        }
+        let opcode = self.read(self.ip).to_i64().ok_or(ErrorKind::InvalidOpcode)?;
+        let ip = self.ip;
+        match opcode % 100 {
+            1 | 2 | 7 | 8 => {
+                let (lhs, rhs) = (self.load(opcode, 0)?, self.load(opcode, 1)?);
+                let val = match opcode % 100 {
+                    1 => &lhs + &rhs,
+                    2 => &lhs * &rhs,
+                    7 => BigInt::from((lhs < rhs) as Mem),
+                    _ => BigInt::from((lhs == rhs) as Mem),
+                };
+                let val = self.limit(val)?;
+                self.store(opcode, 2, val)?;
+                self.ip = ip+4;
+            },
+            3 => {
+                match self.input.pop_front() {
+                    Some(x) => self.store(opcode, 0, x)?,
+                    None => return Ok(Some(BigEvent::NeedInput)),
+                }
+                self.ip = ip+2;
+            },
+            4 => {
+                let val = self.load(opcode, 0)?;
+                self.ip = ip+2;
+                return Ok(Some(BigEvent::Output(val)));
+            },
+            5 | 6 => {
+                if self.load(opcode, 0)?.is_zero() == (opcode % 100 == 6) {
+                    self.ip = to_big_ptr(&self.load(opcode, 1)?)?;
+                } else {
+                    self.ip = ip+3;
+                }
+            },
+            9 => {
+                self.rel_base = self.limit(&self.rel_base + &self.load(opcode, 0)?)?;
+                self.ip = ip+2;
+            },
+            99 => {
+                self.halted = true;
+                return Ok(Some(BigEvent::Halted));
+            },
+            _ => return Err(ErrorKind::InvalidOpcode),
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_bigint() {
+        let two64 = &BigInt::from(1 << 32) * &BigInt::from(1 << 32);
+        assert_eq!(two64.to_string(), "18446744073709551616");
+        assert_eq!(two64.to_i64(), None);
+        assert_eq!((&two64 * &two64).to_string(), "340282366920938463463374607431768211456");
+        assert_eq!((&two64 * &BigInt::from(-1)).to_string(), "-18446744073709551616");
+
+        assert_eq!(&BigInt::from(-5) + &BigInt::from(3), BigInt::from(-2));
+        assert_eq!(&BigInt::from(5) + &BigInt::from(-5), BigInt::default());
+        assert_eq!(&two64 + &BigInt::from(-1), &BigInt::from(0xffff_ffff) * &BigInt::from(0x1_0000_0001));
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!((two64.bits(), BigInt::from(-5).bits(), BigInt::default().bits()), (65, 3, 0));
+        assert_eq!(BigInt::from(i64::MIN).to_string(), i64::MIN.to_string());
+        assert!(BigInt::from(-3) < BigInt::from(2));
+        assert!(&two64 * &BigInt::from(-1) < BigInt::from(i64::MIN));
+    }
+
+    #[test]
+    fn test_big_machine() {
+        let program = assemble("\
+                IN  [x]
+                MUL [x] [x] [x]
+                MUL [x] [x] [x]
+                OUT [x]
+                HLT
+        x:      DATA 0").unwrap();
+        let mut machine = BigMachine::new(program);
+        machine.push_input(BigInt::from(1 << 16));
+        assert_eq!(machine.resume().unwrap(), BigEvent::Output(&BigInt::from(1 << 32) * &BigInt::from(1 << 32)));
+        assert_eq!(machine.resume().unwrap(), BigEvent::Halted);
+
+        // Squaring forever runs into the size limit.
+        let program = assemble("\
+        top:    MUL [x] [x] [x]
+                JT  #1 #top
+        x:      DATA 3").unwrap();
+        let err = BigMachine::new(program).with_max_bits(1000).resume().unwrap_err();
+        assert_eq!((err.kind, err.ip), (ErrorKind::Overflow, 0));
+
+        // Agrees with the regular machine when nothing overflows.
+        let boost = crate::io::parse_intcode_program(&include_str!("../data/day09.in").to_string());
+        let mut machine = BigMachine::new(boost.clone());
+        machine.push_input(BigInt::from(1));
+        let mut expected = vec![];
+        crate::intcode::run_program_splitio(boost, &mut vec![1], &mut expected).unwrap();
+        assert_eq!(machine.resume().unwrap(), BigEvent::Output(BigInt::from(expected[0])));
+    }
+}
@@ -1,162 +1,48 @@
 use aoc2019::io::{slurp_stdin, parse_intcode_program};
-use aoc2019::intcode;
-use aoc2019::intcode::{Mem,Ptr};
-use aoc2019::permutation::Permutations;
-
-fn run_phase(program: Vec<Mem>, phases: Vec<Mem>) -> Result<Mem, String> {
-    let mut last_output = 0;
-    for phase in phases {
-        let mut input = Vec::<Mem>::new();
-        let mut output = Vec::<Mem>::new();
-        let mut machine = intcode::Memory::new(program.clone());
-
-        input.push(last_output);
-        input.push(phase);
-        let mut ip = 0;
-        let mut rel_base = 0;
-        while output.is_empty() {
-            match intcode::step_program_splitio(&mut machine, ip, rel_base, &mut input, &mut output)? {
-                intcode::StepResult::Continue(new_ip, new_rel_base) => { ip = new_ip; rel_base = new_rel_base },
-                intcode::StepResult::End => return Err(String::from("premature end of program")),
-            };
-        }
-        last_output = output[0];
-    }
-    Ok(last_output)
-}
-
-fn best_signal(program: Vec<Mem>) -> Mem {
-    Permutations::new(vec![0, 1, 2, 3, 4])
-        .map(|phases| -> Mem {
-            run_phase(program.clone(), phases).unwrap()
+use aoc2019::intcode::Mem;
+use aoc2019::circuit::{best_permutation, Circuit};
+
+fn run_amplifiers(program: &[Mem], phases: &[Mem], feedback: bool) -> Result<Mem, String> {
+    let mut circuit = Circuit::new();
+    let amps: Vec<_> = phases
+        .iter()
+        .map(|phase| {
+            let amp = circuit.add_program(program.to_vec());
+            circuit.seed(amp, *phase);
+            amp
         })
-        .max()
-        .unwrap()
-}
-
-struct Queue {
-    head: Vec<Mem>,
-    tail: Vec<Mem>,
-}
-
-impl Queue {
-    fn new() -> Self {
-        Queue {head: Vec::new(), tail: Vec::new()}
-    }
-    fn push(&mut self, x: Mem) {
-        self.tail.push(x)
-    }
-    fn pop(&mut self) -> Option<Mem> {
-        if self.head.is_empty() {
-            self.tail.reverse();
-            std::mem::swap(&mut self.head, &mut self.tail);
-        }
-        self.head.pop()
-    }
-    fn is_empty(&self) -> bool {
-        self.head.is_empty() && self.tail.is_empty()
-    }
-    #[allow(dead_code)]
-    fn len(&self) -> usize {
-        self.head.len() + self.tail.len()
-    }
-}
-
-impl intcode::Input for Queue {
-    fn next_input(&mut self) -> Result<Mem, String> {
-        self.pop().ok_or(String::from("no item to pop"))
+        .collect();
+    for pair in amps.windows(2) {
+        circuit.connect(pair[0], pair[1]);
     }
-}
-
-impl intcode::Output for Queue {
-    fn next_output(&mut self, x: Mem) {
-        self.push(x)
-    }
-}
+    let last = *amps.last().unwrap();
+    let signal = if feedback { circuit.connect(last, amps[0]) } else { circuit.tap(last) };
 
-struct Context {
-    memory: intcode::Memory,
-    ip: Ptr,
-    rel_base: Mem,
-    input_queue: Queue,
-    done: bool,
+    circuit.seed(amps[0], 0);
+    circuit.run().map_err(|e| e.to_string())?;
+    circuit.last_value(signal).ok_or(String::from("no output from last amplifier"))
 }
 
-fn all_done(contexts: &[Context]) -> bool {
-    for c in contexts {
-        if !c.done {
-            return false;
-        }
-    }
-    true
+fn run_phase(program: Vec<Mem>, phases: Vec<Mem>) -> Result<Mem, String> {
+    run_amplifiers(&program, &phases, false)
 }
 
-fn get_two(contexts: &mut Vec<Context>, ix0: usize, ix1: usize) -> (&mut Context, &mut Context) {
-    assert_ne!(ix0, ix1);
-    if ix0 < ix1 {
-        let (head, tail) = contexts.split_at_mut(ix1);
-        (&mut head[ix0], &mut tail[0])
-    } else {
-        let (head, tail) = contexts.split_at_mut(ix0);
-        (&mut tail[0], &mut head[ix1])
-    }
+fn run_feedback(program: Vec<Mem>, phases: Vec<Mem>) -> Result<Mem, String> {
+    run_amplifiers(&program, &phases, true)
 }
 
-fn run_feedback(program: Vec<Mem>, phases: Vec<Mem>) -> Result<Mem,String> {
-    let mut contexts: Vec<Context> = Vec::new();
-    for phase in phases {
-        let mut context = Context {
-            memory: intcode::Memory::new(program.clone()),
-            ip: 0,
-            rel_base: 0,
-            input_queue: Queue::new(),
-            done: false,
-        };
-        context.input_queue.push(phase);
-        contexts.push(context);
-    }
-
-    contexts[0].input_queue.push(0);
-
-    let mut ix = 0;
-    while !all_done(&contexts) {
-        let next_ix = (ix + 1) % contexts.len();
-
-        let (cur_context, next_context) = get_two(&mut contexts, ix, next_ix);
-
-        while !cur_context.done &&
-            !(intcode::needs_input(&cur_context.memory, cur_context.ip)? &&
-                cur_context.input_queue.is_empty()) {
-
-            match intcode::step_program_splitio(
-                &mut cur_context.memory,
-                cur_context.ip,
-                cur_context.rel_base,
-                &mut cur_context.input_queue,
-                &mut next_context.input_queue
-            )? {
-                intcode::StepResult::Continue(new_ip, new_rel_base) => {
-                    cur_context.ip = new_ip;
-                    cur_context.rel_base = new_rel_base;
-                },
-                intcode::StepResult::End => {
-                    cur_context.done = true;
-                },
-            }
-        }
-        ix = next_ix;
-    }
-    assert!(!contexts[0].input_queue.is_empty());
-    contexts[0].input_queue.pop().ok_or(String::from("no input at end of feedback"))
+fn best_signal(program: Vec<Mem>) -> Mem {
+    best_permutation(vec![0, 1, 2, 3, 4], |phases| run_phase(program.clone(), phases.to_vec()))
+        .unwrap()
+        .unwrap()
+        .1
 }
 
 fn best_feedback_signal(program: Vec<Mem>) -> Mem {
-    Permutations::new(vec![5, 6, 7, 8, 9])
-        .map(|phases| -> Mem {
-            run_feedback(program.clone(), phases).unwrap()
-        })
-        .max()
+    best_permutation(vec![5, 6, 7, 8, 9], |phases| run_feedback(program.clone(), phases.to_vec()))
+        .unwrap()
         .unwrap()
+        .1
 }
 
 fn main() {
@@ -44,7 +44,7 @@ struct RobotIO {
 }
 
 impl intcode::InputOutput for RobotIO {
-    fn next_input(&mut self) -> Result<i64, String> {
+    fn next_input(&mut self) -> Result<i64, intcode::ErrorKind> {
         let pos = (self.robot.x, self.robot.y);
         match self.robot.colors.get(&pos).unwrap_or(&Color::Black) {
             Color:: Black => Ok(BLACK),
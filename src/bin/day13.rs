@@ -108,11 +108,11 @@ fn get_line() -> io::Result<String> {
 }
 
 impl intcode::InputOutput for GameInput {
-    fn next_input(&mut self) -> Result<i64, String> {
+    fn next_input(&mut self) -> Result<i64, intcode::ErrorKind> {
         print_board(&self.parser.board);
 
         loop {
-            let input = get_line().or(Err(String::from("input failure")))?;
+            let input = get_line().or(Err(intcode::ErrorKind::InputFailed(String::from("input failure"))))?;
 
             match input.get(0..1) {
                 Some("s") => return Ok(0),
@@ -146,14 +146,14 @@ fn find_single(board: &GameBoard, elem: GameElement) -> Option<Point> {
 }
 
 impl intcode::InputOutput for AiInput {
-    fn next_input(&mut self) -> Result<i64, String> {
+    fn next_input(&mut self) -> Result<i64, intcode::ErrorKind> {
         print_board(&self.parser.board);
         std::thread::sleep(std::time::Duration::from_millis(1));
 
         let ball = find_single(&self.parser.board, GameElement::Ball)
-            .ok_or(String::from("no ball"))?;
+            .ok_or(intcode::ErrorKind::InputFailed(String::from("no ball")))?;
         let paddle = find_single(&self.parser.board, GameElement::HorizontalPaddle)
-            .ok_or(String::from("no paddle"))?;
+            .ok_or(intcode::ErrorKind::InputFailed(String::from("no paddle")))?;
 
         if paddle.0 > ball.0 {
             Ok(-1)
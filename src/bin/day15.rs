@@ -135,7 +135,7 @@ const STEP: intcode::Mem = 1;
 const TANK: intcode::Mem = 2;
 
 impl intcode::InputOutput for RobotController {
-    fn next_input(&mut self) -> Result<i64, String> {
+    fn next_input(&mut self) -> Result<i64, intcode::ErrorKind> {
         assert_eq!(lookup(&self.map, self.robot_pos), Terrain::Open);
 
         if !self.is_moving() {
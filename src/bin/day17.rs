@@ -1,22 +1,19 @@
 use aoc2019::io::{slurp_stdin, parse_intcode_program};
 use aoc2019::intcode;
+use aoc2019::ascii::{AsciiInput, AsciiOutput};
 use aoc2019::dir::{Directional, Turn, turn_to, step_to};
 
 type Map = aoc2019::grid::Grid<char>;
 
-struct MapBuilder {
-    grid_builder: aoc2019::grid::GridBuilder<char>,
-}
-
-impl intcode::Output for MapBuilder {
-    fn next_output(&mut self, x: i64) {
-        let c = x as u8 as char;
-        if c == '\n' {
-            self.grid_builder.eol();
-        } else {
-            self.grid_builder.push(c);
+fn build_map(text: &str) -> Map {
+    let mut grid_builder = aoc2019::grid::GridBuilder::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        for c in line.chars() {
+            grid_builder.push(c);
         }
+        grid_builder.eol();
     }
+    grid_builder.build('.')
 }
 
 fn find_intersections(map: &Map) -> Vec<(i64, i64)> {
@@ -137,9 +134,9 @@ fn break_down_commands<'a>(cmds: &'a [String], routines: &mut Vec<&'a [String]>,
 fn main() {
     let program = parse_intcode_program(&slurp_stdin());
 
-    let mut map_builder = MapBuilder { grid_builder: aoc2019::grid::GridBuilder::new() };
-    intcode::run_program_splitio(program.clone(), &mut vec![], &mut map_builder).unwrap();
-    let map = map_builder.grid_builder.build('.');
+    let mut camera = AsciiOutput::new();
+    intcode::run_program_splitio(program.clone(), &mut vec![], &mut camera).unwrap();
+    let map = build_map(camera.text());
 
     let intersections = find_intersections(&map);
 
@@ -188,12 +185,11 @@ fn main() {
 
     // print!("{}", input_string);
 
-    let mut out = vec![];
+    let mut out = AsciiOutput::new();
     let mut prog = program.clone();
     prog[0] = 2;
-    let mut input: Vec<intcode::Mem> = input_string.chars().map(|c| c as intcode::Mem).collect();
-    input.reverse();
+    let mut input = AsciiInput::from_text(&input_string);
 
     intcode::run_program_splitio(prog, &mut input, &mut out).unwrap();
-    println!("{}", out.last().unwrap());
+    println!("{}", out.values().last().unwrap());
 }
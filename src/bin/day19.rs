@@ -1,17 +1,33 @@
 use aoc2019::io::{parse_intcode_program, slurp_stdin};
-use aoc2019::intcode;
+use aoc2019::intcode::{Event, Machine};
+use aoc2019::profile::{Profiler, SortBy};
 
-fn scan(program: Vec<intcode::Mem>, x: i64, y: i64) -> bool {
-    let mut out = vec![];
-    intcode::run_program_splitio(program.clone(), &mut vec![y,x], &mut out).unwrap();
-    *out.first().unwrap() > 0
+struct Drone {
+    program: Machine,
+    profiler: Option<Profiler>,
 }
 
-fn beam_limits(program: &Vec<intcode::Mem>, y: i64) -> (i64, i64) {
+impl Drone {
+    fn scan(&mut self, x: i64, y: i64) -> bool {
+        let mut drone = self.program.fork();
+        drone.push_input(x);
+        drone.push_input(y);
+        let event = match self.profiler.as_mut() {
+            Some(profiler) => drone.resume_traced(profiler),
+            None => drone.resume(),
+        };
+        match event.unwrap() {
+            Event::Output(val) => val > 0,
+            _ => panic!("drone produced no output"),
+        }
+    }
+}
+
+fn beam_limits(drone: &mut Drone, y: i64) -> (i64, i64) {
     let mut x = 0;
     let startx = {
         loop {
-            if scan(program.clone(), x, y) {
+            if drone.scan(x, y) {
                 break x;
             }
             x += 1;
@@ -19,7 +35,7 @@ fn beam_limits(program: &Vec<intcode::Mem>, y: i64) -> (i64, i64) {
     };
     let endx = {
         loop {
-            if !scan(program.clone(), x, y) {
+            if !drone.scan(x, y) {
                 break x;
             }
             x += 1;
@@ -28,11 +44,11 @@ fn beam_limits(program: &Vec<intcode::Mem>, y: i64) -> (i64, i64) {
     (startx, endx)
 }
 
-fn beam_limits_guess(program: &Vec<intcode::Mem>, y: i64, x0: i64, x1: i64) -> (i64, i64) {
+fn beam_limits_guess(drone: &mut Drone, y: i64, x0: i64, x1: i64) -> (i64, i64) {
     let mut x = x0;
     let startx = {
         loop {
-            if scan(program.clone(), x, y) {
+            if drone.scan(x, y) {
                 break x;
             }
             x += 1;
@@ -41,7 +57,7 @@ fn beam_limits_guess(program: &Vec<intcode::Mem>, y: i64, x0: i64, x1: i64) -> (
     x = x1;
     let endx = {
         loop {
-            if !scan(program.clone(), x, y) {
+            if !drone.scan(x, y) {
                 break x;
             }
             x += 1;
@@ -50,20 +66,20 @@ fn beam_limits_guess(program: &Vec<intcode::Mem>, y: i64, x0: i64, x1: i64) -> (
     (startx, endx)
 }
 
-fn find_square(program: &Vec<intcode::Mem>) -> (i64, i64) {
+fn find_square(drone: &mut Drone) -> (i64, i64) {
     let mut y = 100;
-    let (mut x0, mut x1) = beam_limits(program, y);
+    let (mut x0, mut x1) = beam_limits(drone, y);
     while x1 - x0 < 100 {
         y += 1;
-        let xs = beam_limits_guess(program, y, x0, x1);
+        let xs = beam_limits_guess(drone, y, x0, x1);
         x0 = xs.0;
         x1 = xs.1;
     }
-    let (mut xx0, mut xx1) = beam_limits_guess(program, y+99, x0, x1);
+    let (mut xx0, mut xx1) = beam_limits_guess(drone, y+99, x0, x1);
     while !(xx0+99 < x1) {
         y += 1;
-        let p = beam_limits_guess(program, y, x0, x1);
-        let pp = beam_limits_guess(program, y+99, xx0, xx1);
+        let p = beam_limits_guess(drone, y, x0, x1);
+        let pp = beam_limits_guess(drone, y+99, xx0, xx1);
         x0 = p.0;
         x1 = p.1;
         xx0 = pp.0;
@@ -73,12 +89,14 @@ fn find_square(program: &Vec<intcode::Mem>) -> (i64, i64) {
 }
 
 fn main() {
-    let program = parse_intcode_program(&slurp_stdin());
+    let program = Machine::new(parse_intcode_program(&slurp_stdin()));
+    let profile = std::env::args().any(|arg| arg == "--profile");
+    let mut drone = Drone { program, profiler: if profile { Some(Profiler::new()) } else { None } };
 
     let mut builder = aoc2019::grid::GridBuilder::new();
     for y in 0..50 {
         for x in 0..50 {
-            let val = scan(program.clone(), x, y);
+            let val = drone.scan(x, y);
             builder.push(vec!['.','#'][val as usize]);
         }
         builder.eol();
@@ -87,6 +105,10 @@ fn main() {
     println!("{}", grid.find_all(&'#').len());
     //println!("{}", grid);
 
-    let (x, y) = find_square(&program);
+    let (x, y) = find_square(&mut drone);
     println!("{}", x * 10000 + y);
+
+    if let Some(profiler) = drone.profiler {
+        eprint!("{}", profiler.report(SortBy::Count, 10));
+    }
 }
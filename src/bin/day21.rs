@@ -1,27 +1,22 @@
 use aoc2019::io::{slurp_stdin, parse_intcode_program};
 use aoc2019::intcode;
+use aoc2019::ascii::{AsciiInput, AsciiOutput};
 
 fn run_springcode(program: Vec<intcode::Mem>, springcode: &str) {
-    let mut input = Vec::new();
-    for c in springcode.chars() {
-        input.push(c as u8 as intcode::Mem);
-    }
-    input.reverse();
-
-    let mut output = Vec::<intcode::Mem>::new();
+    let mut input = AsciiInput::from_text(springcode);
+    let mut output = AsciiOutput::new();
     intcode::run_program_splitio(program, &mut input, &mut output).unwrap();
-    let mut iter = output.into_iter().peekable();
 
-    for _ in 0..4 {
-        while iter.next().unwrap() as u8 as char != '\n' {}
-    }
-    let ans = *iter.peek().unwrap();
-    if 0 <= ans && ans < 256 {
-        for x in iter {
-            print!("{}", x as u8 as char);
-        }
-    } else {
-        println!("{}", ans);
+    match output.values().last() {
+        Some(ans) => println!("{}", ans),
+        None => {
+            for _ in 0..4 {
+                output.read_line();
+            }
+            while let Some(line) = output.read_line() {
+                println!("{}", line);
+            }
+        },
     }
 }
 
@@ -1,99 +1,11 @@
 use std::fs::File;
-use std::io::{Read, BufRead};
+use std::io::Read;
 use std::io;
+use std::path::Path;
 use aoc2019::io::parse_intcode_program;
 use aoc2019::intcode;
-use std::collections::VecDeque;
-use aoc2019::intcode::Input;
-
-fn get_line() -> io::Result<String> {
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
-    let mut buf = String::new();
-
-    handle.read_line(&mut buf)?;
-    Ok(buf)
-}
-
-fn to_queue(s: &str) -> VecDeque<char> {
-    let mut ret = VecDeque::new();
-    for c in s.chars() {
-        ret.push_back(c);
-    }
-    ret
-}
-
-struct ConsoleOutput {
-}
-
-impl intcode::Output for ConsoleOutput {
-    fn next_output(&mut self, x: i64) {
-        print!("{}", x as u8 as char);
-    }
-}
-
-struct BasicInput {
-    buf: VecDeque<char>,
-}
-
-impl intcode::Input for BasicInput {
-    fn next_input(&mut self) -> Result<i64, String> {
-        let c = self.buf.pop_front().ok_or(String::from("no input left"))?;
-        Ok(c as u8 as i64)
-    }
-}
-
-struct ConsoleInput {
-    input: BasicInput,
-}
-
-impl intcode::Input for ConsoleInput {
-    fn next_input(&mut self) -> Result<i64, String> {
-        if self.input.buf.is_empty() {
-            self.input = BasicInput { buf: to_queue(&get_line().unwrap()) };
-        }
-        let c = self.input.next_input()? as u8 as char;
-        print!("{}", c);
-        Ok(c as u8 as i64)
-    }
-}
-
-struct Experimenter {
-    input: BasicInput,
-    current_room: String,
-    rooms: Vec<String>,
-}
-
-impl Experimenter {
-    fn new(script1: &str, script2: &str) -> Self {
-        let mut queue = to_queue(script1);
-        queue.append(&mut to_queue(script2));
-        Experimenter {
-            input: BasicInput { buf: queue },
-            current_room: String::from(""),
-            rooms: Vec::new()
-        }
-    }
-}
-
-impl intcode::InputOutput for Experimenter {
-    fn next_input(&mut self) -> Result<i64, String> {
-        self.input.next_input()
-    }
-
-    fn next_output(&mut self, x: i64) {
-        let c = x as u8 as char;
-        if c == '=' && self.current_room.is_empty() {
-            self.current_room.push(c);
-        } else if c == '\n' && !self.current_room.is_empty() {
-            let mut tmp = String::from("");
-            std::mem::swap(&mut tmp, &mut self.current_room);
-            self.rooms.push(tmp);
-        } else if !self.current_room.is_empty() {
-            self.current_room.push(c);
-        }
-    }
-}
+use aoc2019::ascii::{AsciiIO, AsciiInput, AsciiOutput};
+use aoc2019::replay::{self, Log, Recorder};
 
 const FULL_SCRIPT: &str = "\
 south\n\
@@ -141,13 +53,19 @@ fn experiment_script(items: &[&str], experiment: usize, direction: &str) -> Stri
     ret
 }
 
+const EXPERIMENT_STEPS: u64 = 10_000_000;
+
 #[allow(dead_code)]
 fn run_experiment(program: &Vec<intcode::Mem>) {
     for experiment in 0..(1 << ITEMS.len()) {
         let script2 = experiment_script(&ITEMS, experiment, "south");
-        let mut experimenter = Experimenter::new(FULL_SCRIPT, &script2);
-        let _ret = intcode::run_program(program.clone(), &mut experimenter);
-        if experimenter.rooms.last().unwrap() != ROOM {
+        let mut input = AsciiInput::from_text(FULL_SCRIPT);
+        input.push_str(&script2);
+        let mut io = AsciiIO::new(input, AsciiOutput::new());
+        // Some rooms trap the droid in an infinite loop, so don't wait forever.
+        let _ret = intcode::run_program_bounded(program.clone(), &mut io, intcode::Budget::steps(EXPERIMENT_STEPS));
+        let last_room = io.output.text().lines().filter(|line| line.starts_with("==")).last();
+        if last_room != Some(ROOM) {
             println!("{}", experiment);
             println!("{}", script2);
             break;
@@ -155,10 +73,15 @@ fn run_experiment(program: &Vec<intcode::Mem>) {
     }
 }
 
-fn play(program: &Vec<intcode::Mem>, script: &str) {
-    let mut input = ConsoleInput { input: BasicInput { buf: to_queue(script) } };
-    let mut output = ConsoleOutput {};
-    intcode::run_program_splitio(program.clone(), &mut input, &mut output).unwrap();
+fn play(program: &Vec<intcode::Mem>, script: &str, record: Option<&Path>) {
+    let input = AsciiInput::from_text(script).with_reader(io::stdin().lock()).with_echo();
+    let mut io = AsciiIO::new(input, AsciiOutput::new().with_echo());
+    let mut recorder = Recorder::new();
+    let result = intcode::run_program_traced(program.clone(), &mut io, &mut recorder);
+    if let Some(path) = record {
+        recorder.log().save(path).unwrap();
+    }
+    result.unwrap();
 }
 
 const SCRIPT: &str = "\
@@ -195,5 +118,23 @@ fn main() {
     let program = parse_intcode_program(&program_input);
 
     //run_experiment(&program);
-    play(&program, SCRIPT);
+    let args: Vec<String> = std::env::args().collect();
+    let path = || args.get(2).map(Path::new).unwrap_or_else(|| {
+        eprintln!("usage: day25 [--record PATH | --replay PATH]");
+        std::process::exit(2);
+    });
+    match args.get(1).map(String::as_str) {
+        Some("--replay") => {
+            let log = Log::load(path()).unwrap();
+            match replay::replay(program, &log) {
+                Ok(()) => println!("replay matches"),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                },
+            }
+        },
+        Some("--record") => play(&program, SCRIPT, Some(path())),
+        _ => play(&program, SCRIPT, None),
+    }
 }
@@ -0,0 +1,28 @@
+use std::fs::File;
+use std::io::Read;
+use aoc2019::io::{parse_intcode_program, slurp_stdin};
+use aoc2019::disasm::listing;
+use aoc2019::cfg::Cfg;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let dot = args.iter().any(|arg| arg == "--dot");
+    let source = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => {
+            let mut buf = String::new();
+            File::open(path)
+                .unwrap()
+                .read_to_string(&mut buf)
+                .unwrap();
+            buf
+        },
+        None => slurp_stdin(),
+    };
+    let program = parse_intcode_program(&source);
+
+    if dot {
+        print!("{}", Cfg::build(&program).to_dot());
+    } else {
+        print!("{}", listing(&program));
+    }
+}
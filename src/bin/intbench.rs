@@ -0,0 +1,126 @@
+// Times the cached engine against the interpreter. Run from the repository
+// root, in release mode:
+//
+//     cargo run --release --bin intbench [ROUNDS]
+
+use std::fs;
+use std::time::{Duration, Instant};
+use aoc2019::cached::CachedMachine;
+use aoc2019::intcode::{Event, Machine, Mem};
+use aoc2019::io::parse_intcode_program;
+use aoc2019::lang::compile;
+
+fn load(path: &str) -> Vec<Mem> {
+    parse_intcode_program(&fs::read_to_string(path).unwrap())
+}
+
+fn time<F: FnMut() -> Mem>(rounds: u32, mut f: F) -> (Duration, Mem) {
+    let start = Instant::now();
+    let mut result = 0;
+    for _ in 0..rounds {
+        result = f();
+    }
+    (start.elapsed() / rounds, result)
+}
+
+fn report(name: &str, interpreted: (Duration, Mem), cached: (Duration, Mem)) {
+    assert_eq!(interpreted.1, cached.1, "{}: engines disagree", name);
+    println!("{:<12} {:>12.3?} {:>12.3?} {:>8.2}x",
+             name, interpreted.0, cached.0,
+             interpreted.0.as_secs_f64() / cached.0.as_secs_f64());
+}
+
+fn first_output(event: Event) -> Mem {
+    match event {
+        Event::Output(x) => x,
+        e => panic!("expected output, got {:?}", e),
+    }
+}
+
+// The BOOST program in sensor boost mode.
+fn boost_interpreted(program: &Machine) -> Mem {
+    let mut machine = program.fork();
+    machine.push_input(2);
+    first_output(machine.resume().unwrap())
+}
+
+fn boost_cached(program: &CachedMachine) -> Mem {
+    let mut machine = program.fork();
+    machine.push_input(2);
+    first_output(machine.resume().unwrap())
+}
+
+// The size of the beam within 50x50, one fresh drone per point.
+fn beam_interpreted(program: &Machine) -> Mem {
+    let mut count = 0;
+    for y in 0..50 {
+        for x in 0..50 {
+            let mut drone = program.fork();
+            drone.push_input(x);
+            drone.push_input(y);
+            count += first_output(drone.resume().unwrap());
+        }
+    }
+    count
+}
+
+fn beam_cached(program: &CachedMachine) -> Mem {
+    let mut count = 0;
+    for y in 0..50 {
+        for x in 0..50 {
+            let mut drone = program.fork();
+            drone.push_input(x);
+            drone.push_input(y);
+            count += first_output(drone.resume().unwrap());
+        }
+    }
+    count
+}
+
+// Counts the primes below 20000 with a sieve, compiled from the small
+// language, which indexes arrays through self-modifying code.
+const SIEVE: &str = "
+    var composite[20000];
+
+    fn main() {
+        var count = 0;
+        var i = 2;
+        while (i < 20000) {
+            if (!composite[i]) {
+                count = count + 1;
+                var j = i * i;
+                while (j < 20000) {
+                    composite[j] = 1;
+                    j = j + i;
+                }
+            }
+            i = i + 1;
+        }
+        output(count);
+    }";
+
+fn sieve_interpreted(program: &Machine) -> Mem {
+    first_output(program.fork().resume().unwrap())
+}
+
+fn sieve_cached(program: &CachedMachine) -> Mem {
+    first_output(program.fork().resume().unwrap())
+}
+
+fn main() {
+    let rounds = std::env::args().nth(1).map_or(10, |s| s.parse().expect("invalid round count"));
+
+    println!("{:<12} {:>12} {:>12} {:>9}", "program", "interpreter", "cached", "speedup");
+
+    let boost = Machine::new(load("data/day09.in"));
+    let cached = CachedMachine::from(boost.clone());
+    report("day09 boost", time(rounds, || boost_interpreted(&boost)), time(rounds, || boost_cached(&cached)));
+
+    let beam = Machine::new(load("data/day19.in"));
+    let cached = CachedMachine::from(beam.clone());
+    report("day19 beam", time(rounds, || beam_interpreted(&beam)), time(rounds, || beam_cached(&cached)));
+
+    let sieve = Machine::new(compile(SIEVE).unwrap());
+    let cached = CachedMachine::from(sieve.clone());
+    report("sieve", time(rounds, || sieve_interpreted(&sieve)), time(rounds, || sieve_cached(&cached)));
+}
@@ -0,0 +1,287 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::exit;
+use aoc2019::intcode::{Event, Machine, Mem, Ptr};
+use aoc2019::optimize::optimize;
+use aoc2019::trace::TraceWriter;
+
+const USAGE: &str = "\
+usage: intcode [OPTIONS] PROGRAM [INPUT...]
+
+Runs an intcode program. INPUTs are numbers, or lines of text with --ascii.
+
+  --ascii              read and write ASCII text instead of numbers
+  --patch ADDR=VAL     set a memory cell before running (may be repeated)
+  --input-file PATH    read input from PATH, after any INPUT arguments
+                       (- for standard input)
+  --max-steps N        give up after N instructions
+  --dump ADDR[..END]   print memory from ADDR up to END (exclusive) once
+                       the program halts (may be repeated)
+  --optimize           simplify the program first, unless it may modify
+                       itself
+  --trace              print every executed instruction to stderr
+
+exit status: 0 halted or output closed, 2 waiting for input, 3 fault,
+4 step limit, 5 I/O error, 64 usage
+";
+
+const EXIT_HALTED: i32 = 0;
+const EXIT_STARVED: i32 = 2;
+const EXIT_FAULT: i32 = 3;
+const EXIT_STEP_LIMIT: i32 = 4;
+const EXIT_IO: i32 = 5;
+const EXIT_USAGE: i32 = 64;
+
+#[derive(Default)]
+struct Options {
+    ascii: bool,
+    patches: Vec<(Ptr, Mem)>,
+    input_file: Option<String>,
+    max_steps: Option<u64>,
+    dumps: Vec<(Ptr, Ptr)>,
+    optimize: bool,
+    trace: bool,
+    program: String,
+    inputs: Vec<String>,
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number '{}'", s))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--ascii" => options.ascii = true,
+            "--optimize" => options.optimize = true,
+            "--trace" => options.trace = true,
+            "--patch" => {
+                let patch = value()?;
+                let (addr, val) = patch.split_once('=').ok_or(format!("invalid patch '{}'", patch))?;
+                options.patches.push((parse_number(addr)?, parse_number(val)?));
+            },
+            "--input-file" => options.input_file = Some(value()?.clone()),
+            "--max-steps" => options.max_steps = Some(parse_number(value()?)?),
+            "--dump" => {
+                let range = value()?;
+                let (start, end) = match range.split_once("..") {
+                    Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+                    None => {
+                        let addr: Ptr = parse_number(range)?;
+                        (addr, addr.saturating_add(1))
+                    },
+                };
+                options.dumps.push((start, end));
+            },
+            "--help" => {
+                print!("{}", USAGE);
+                exit(EXIT_HALTED);
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if positional.is_empty() {
+        return Err(String::from("no program given"));
+    }
+    options.program = positional.remove(0);
+    options.inputs = positional;
+    Ok(options)
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    let mut text = String::new();
+    let result = if path == "-" {
+        io::stdin().read_to_string(&mut text).map(|_| ())
+    } else {
+        fs::File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map(|_| ())
+    };
+    result.map_err(|e| format!("{}: {}", path, e)).map(|_| text)
+}
+
+fn ascii_input(text: &str) -> Result<Vec<Mem>, String> {
+    text.chars()
+        .map(|c| if c.is_ascii() { Ok(c as Mem) } else { Err(format!("non-ASCII character {:?} in input", c)) })
+        .collect()
+}
+
+fn numeric_input(text: &str) -> Result<Vec<Mem>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(parse_number)
+        .collect()
+}
+
+/// Why setup failed: the exit status and a message.
+type Failure = (i32, String);
+
+fn usage(msg: String) -> Failure {
+    (EXIT_USAGE, msg)
+}
+
+fn inputs(options: &Options) -> Result<Vec<Mem>, Failure> {
+    let mut values = Vec::new();
+    for arg in &options.inputs {
+        if options.ascii {
+            values.extend(ascii_input(arg).map_err(usage)?);
+            values.push('\n' as Mem);
+        } else {
+            values.extend(numeric_input(arg).map_err(usage)?);
+        }
+    }
+    if let Some(path) = &options.input_file {
+        let text = read_file(path).map_err(|e| (EXIT_IO, e))?;
+        let parsed = if options.ascii { ascii_input(&text) } else { numeric_input(&text) };
+        values.extend(parsed.map_err(|e| usage(format!("{}: {}", path, e)))?);
+    }
+    Ok(values)
+}
+
+fn setup(options: &Options) -> Result<Machine, Failure> {
+    let text = read_file(&options.program).map_err(|e| (EXIT_IO, e))?;
+    let mut program = numeric_input(&text).map_err(|e| usage(format!("{}: {}", options.program, e)))?;
+    // Patches beyond the image go through the machine's sparse memory. The
+    // optimizer only sees the image, so it can't take those into account.
+    let mut far = Vec::new();
+    for &(addr, val) in &options.patches {
+        match program.get_mut(addr) {
+            Some(cell) => *cell = val,
+            None if options.optimize => return Err(usage(format!("can't patch {} beyond the program with --optimize", addr))),
+            None => far.push((addr, val)),
+        }
+    }
+    if options.optimize {
+        match optimize(&program) {
+            Ok((optimized, _)) => program = optimized,
+            Err(e) => eprintln!("intcode: not optimizing: {}", e),
+        }
+    }
+    let mut machine = Machine::new(program);
+    for (addr, val) in far {
+        machine.poke(addr, val);
+    }
+    for x in inputs(options)? {
+        machine.push_input(x);
+    }
+    Ok(machine)
+}
+
+fn write_output(out: &mut impl Write, ascii: bool, x: Mem) -> io::Result<()> {
+    if ascii && (0..128).contains(&x) {
+        write!(out, "{}", x as u8 as char)
+    } else {
+        writeln!(out, "{}", x)
+    }
+}
+
+fn dump(out: &mut impl Write, machine: &Machine, dumps: &[(Ptr, Ptr)]) -> io::Result<()> {
+    for &(start, end) in dumps {
+        for addr in start..end {
+            writeln!(out, "{}", machine.memory().read(addr))?;
+        }
+    }
+    Ok(())
+}
+
+fn run(options: &Options, mut machine: Machine, out: &mut impl Write) -> i32 {
+    let mut tracer = if options.trace { Some(TraceWriter::new(io::stderr())) } else { None };
+    let mut steps = 0;
+    let (status, written) = loop {
+        if options.max_steps.is_some_and(|max| steps >= max) {
+            eprintln!("step limit reached at ip {}", machine.ip());
+            break (EXIT_STEP_LIMIT, Ok(()));
+        }
+        let event = match tracer.as_mut() {
+            Some(tracer) => machine.step_traced(tracer),
+            None => machine.step(),
+        };
+        match event {
+            Ok(None) => (),
+            Ok(Some(Event::Output(x))) => {
+                if let Err(e) = write_output(out, options.ascii, x) {
+                    break (EXIT_HALTED, Err(e));
+                }
+            },
+            Ok(Some(Event::NeedInput)) => {
+                eprintln!("out of input at ip {}", machine.ip());
+                break (EXIT_STARVED, Ok(()));
+            },
+            Ok(Some(Event::Halted)) => break (EXIT_HALTED, dump(out, &machine, &options.dumps)),
+            Err(e) => {
+                eprintln!("{}", e);
+                break (EXIT_FAULT, Ok(()));
+            },
+        }
+        steps += 1;
+    };
+    let written = written.and_then(|()| out.flush());
+    let traced = tracer.map_or(Ok(()), |tracer| tracer.finish().map(|_| ()));
+    match written.and(traced) {
+        // Whoever reads our output has seen enough.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => EXIT_HALTED,
+        Err(e) => {
+            eprintln!("intcode: {}", e);
+            EXIT_IO
+        },
+        Ok(()) => status,
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = parse_args(&args).unwrap_or_else(|msg| {
+        eprintln!("intcode: {}", msg);
+        eprint!("{}", USAGE);
+        exit(EXIT_USAGE);
+    });
+    let machine = setup(&options).unwrap_or_else(|(status, msg)| {
+        eprintln!("intcode: {}", msg);
+        exit(status);
+    });
+    exit(run(&options, machine, &mut io::stdout().lock()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        parse_args(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    struct Failing(io::ErrorKind);
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(self.0))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_day02() {
+        let options = options(&["--patch", "1=12", "--patch", "2=2", "--dump", "0..3", "data/day02.in"]);
+        let mut out = Vec::new();
+        assert_eq!(run(&options, setup(&options).unwrap(), &mut out), EXIT_HALTED);
+        assert_eq!(String::from_utf8(out).unwrap(), "3085697\n12\n2\n");
+
+        let options = self::options(&["data/missing.in"]);
+        assert_eq!(setup(&options).err().map(|(status, _)| status), Some(EXIT_IO));
+    }
+
+    #[test]
+    fn test_closed_output() {
+        // OUT #1; JT #1 #0
+        let options = options(&["-"]);
+        let output = || Machine::new(vec![104, 1, 1105, 1, 0]);
+        assert_eq!(run(&options, output(), &mut Failing(io::ErrorKind::BrokenPipe)), EXIT_HALTED);
+        assert_eq!(run(&options, output(), &mut Failing(io::ErrorKind::Other)), EXIT_IO);
+    }
+}
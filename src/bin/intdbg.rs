@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use aoc2019::disasm::disassemble_range;
+use aoc2019::history::History;
+use aoc2019::memsearch::{Filter, MemorySearch, Pins};
+use aoc2019::snapshot;
+use aoc2019::intcode::{Event, Machine, Mem, Ptr};
+use aoc2019::io::parse_intcode_program;
+
+const HELP: &str = "\
+s [N]           step N instructions (default 1)
+c               continue until a breakpoint, watch, input request or halt
+bs [N]          step back N instructions (default 1)
+rb ADDR         run back to the last time ADDR was about to execute
+lw ADDR         show the last instruction that wrote to a memory cell
+b ADDR          set breakpoint
+d ADDR          delete breakpoint
+w ADDR          watch memory cell (stop when it changes)
+u ADDR          unwatch memory cell
+l               list breakpoints, watches and pins
+r               print registers
+x ADDR [N]      disassemble N words (default 20) starting at ADDR
+m ADDR [N]      dump N memory cells (default 10) starting at ADDR
+i N...          queue numeric input
+a TEXT          queue TEXT and a newline as ASCII input
+o ascii|num     show output as characters or numbers
+find new        start a memory search with every cell as a candidate
+find RULE       keep cells that are changed, unchanged, inc, dec, out
+                (equal to the last output) or = N since the last find
+find            list the remaining candidates
+pin ADDR VAL    hold a memory cell at VAL
+unpin ADDR      release a pinned cell
+poke ADDR VAL   write VAL to a memory cell once
+save PATH       write a snapshot of the machine to PATH
+load PATH       replace the machine with a snapshot from PATH
+q               quit
+";
+
+/// How many executed instructions can be stepped back over.
+const HISTORY_STEPS: usize = 1_000_000;
+
+#[derive(PartialEq)]
+enum OutputMode {
+    Ascii,
+    Numeric,
+}
+
+enum Stop {
+    Breakpoint,
+    Watch(Ptr, Mem, Mem),
+    NeedInput,
+    Halted,
+    Steps,
+}
+
+struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<Ptr>,
+    watches: BTreeMap<Ptr, Mem>,
+    output_mode: OutputMode,
+    last_output: Option<Mem>,
+    search: Option<MemorySearch>,
+    pins: Pins,
+    history: History,
+}
+
+impl Debugger {
+    fn new(program: Vec<Mem>) -> Self {
+        Debugger {
+            machine: Machine::new(program),
+            breakpoints: BTreeSet::new(),
+            watches: BTreeMap::new(),
+            output_mode: OutputMode::Numeric,
+            last_output: None,
+            search: None,
+            pins: Pins::new(),
+            history: History::with_capacity(HISTORY_STEPS),
+        }
+    }
+
+    fn show_output(&mut self, x: Mem) {
+        self.last_output = Some(x);
+        if self.output_mode == OutputMode::Ascii && (0..128).contains(&x) {
+            print!("{}", x as u8 as char);
+        } else {
+            println!("output: {}", x);
+        }
+    }
+
+    fn check_watches(&mut self) -> Option<Stop> {
+        for (addr, old) in self.watches.iter_mut() {
+            let new = self.machine.memory().read(*addr);
+            if new != *old {
+                let stop = Stop::Watch(*addr, *old, new);
+                *old = new;
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    /// Runs at most `limit` instructions, stopping early on events,
+    /// watches and (after the first instruction) breakpoints.
+    fn run(&mut self, limit: Option<usize>) -> Result<Stop, String> {
+        let mut count = 0;
+        loop {
+            if limit == Some(count) {
+                return Ok(Stop::Steps);
+            }
+            if count > 0 && self.breakpoints.contains(&self.machine.ip()) {
+                return Ok(Stop::Breakpoint);
+            }
+            let event = self.machine.step_traced(&mut self.history).map_err(|e| e.to_string())?;
+            self.pins.apply(&mut self.machine);
+            match event {
+                Some(Event::Output(x)) => self.show_output(x),
+                Some(Event::NeedInput) => return Ok(Stop::NeedInput),
+                Some(Event::Halted) => return Ok(Stop::Halted),
+                None => (),
+            }
+            count += 1;
+            if let Some(stop) = self.check_watches() {
+                return Ok(stop);
+            }
+        }
+    }
+
+    /// Called after stepping back, so that watches don't fire on the
+    /// restored values.
+    fn resync_watches(&mut self) {
+        for (addr, val) in self.watches.iter_mut() {
+            *val = self.machine.memory().read(*addr);
+        }
+    }
+
+    fn print_current(&self) {
+        let mem = self.machine.memory();
+        let ip = self.machine.ip();
+        println!("{}", disassemble_range(mem, ip, ip + 4).remove(0));
+    }
+
+    fn report(&self, stop: Stop) {
+        io::stdout().flush().unwrap();
+        match stop {
+            Stop::Breakpoint => println!("breakpoint at {}", self.machine.ip()),
+            Stop::Watch(addr, old, new) => println!("[{}] changed {} -> {}", addr, old, new),
+            Stop::NeedInput => println!("waiting for input"),
+            Stop::Halted => println!("halted"),
+            Stop::Steps => (),
+        }
+        if !self.machine.is_halted() {
+            self.print_current();
+        }
+    }
+
+    fn find(&mut self, args: &[&str]) -> Result<(), String> {
+        let filter = match args {
+            [] => None,
+            ["new"] => {
+                self.search = Some(MemorySearch::new(self.machine.memory()));
+                None
+            },
+            ["changed"] => Some(Filter::Changed),
+            ["unchanged"] => Some(Filter::Unchanged),
+            ["inc"] => Some(Filter::Increased),
+            ["dec"] => Some(Filter::Decreased),
+            ["out"] => Some(Filter::Equals(self.last_output.ok_or("no output yet")?)),
+            ["=", n] => Some(Filter::Equals(n.parse().map_err(|_| format!("invalid number '{}'", n))?)),
+            _ => return Err(String::from("usage: find [new|changed|unchanged|inc|dec|out|= N]")),
+        };
+        let search = self.search.as_mut().ok_or("no search in progress, try 'find new'")?;
+        if let Some(filter) = filter {
+            search.filter(self.machine.memory(), filter);
+        }
+        println!("{} candidates", search.len());
+        for (addr, val) in search.candidates().iter().take(20) {
+            println!("[{}] = {}", addr, val);
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Ok(true),
+        };
+        let args: Vec<&str> = words.collect();
+        let num = |ix: usize| -> Result<Option<Mem>, String> {
+            match args.get(ix) {
+                Some(arg) => arg.parse().map(Some).map_err(|_| format!("invalid number '{}'", arg)),
+                None => Ok(None),
+            }
+        };
+        let addr = |ix: usize| -> Result<Ptr, String> {
+            match num(ix)? {
+                Some(x) if x >= 0 => Ok(x as Ptr),
+                Some(x) => Err(format!("invalid address {}", x)),
+                None => Err(String::from("missing address")),
+            }
+        };
+        let count = |ix: usize, default: usize| -> Result<usize, String> {
+            match num(ix)? {
+                Some(x) if x >= 0 => Ok(x as usize),
+                Some(x) => Err(format!("invalid count {}", x)),
+                None => Ok(default),
+            }
+        };
+
+        match cmd {
+            "s" => {
+                let n = count(0, 1)?;
+                let stop = self.run(Some(n))?;
+                self.report(stop);
+            },
+            "c" => {
+                let stop = self.run(None)?;
+                self.report(stop);
+            },
+            "bs" => {
+                let n = count(0, 1)?;
+                let mut undone = 0;
+                while undone < n && self.history.step_back(&mut self.machine).is_some() {
+                    undone += 1;
+                }
+                if undone < n {
+                    println!("reached the start of the history");
+                }
+                self.resync_watches();
+                self.print_current();
+            },
+            "rb" => {
+                let a = addr(0)?;
+                match self.history.run_back_to(&mut self.machine, a) {
+                    Some(n) => println!("went back {} steps", n),
+                    None => return Err(format!("{} was not executed within the history", a)),
+                }
+                self.resync_watches();
+                self.print_current();
+            },
+            "lw" => {
+                let a = addr(0)?;
+                match self.history.last_write(a) {
+                    Some((step, record)) => println!("step {}: {}", step, record),
+                    None => println!("[{}] was not written within the history", a),
+                }
+            },
+            "b" => { self.breakpoints.insert(addr(0)?); },
+            "d" => { self.breakpoints.remove(&addr(0)?); },
+            "w" => {
+                let a = addr(0)?;
+                self.watches.insert(a, self.machine.memory().read(a));
+            },
+            "u" => { self.watches.remove(&addr(0)?); },
+            "l" => {
+                for b in &self.breakpoints {
+                    println!("break {}", b);
+                }
+                for (w, val) in &self.watches {
+                    println!("watch [{}] = {}", w, val);
+                }
+                for (p, val) in self.pins.iter() {
+                    println!("pin [{}] = {}", p, val);
+                }
+            },
+            "r" => {
+                println!("ip={} rel_base={} pending_input={}{}",
+                         self.machine.ip(),
+                         self.machine.rel_base(),
+                         self.machine.pending_input(),
+                         if self.machine.is_halted() { " (halted)" } else { "" });
+            },
+            "x" => {
+                let start = addr(0)?;
+                let n = count(1, 20)?;
+                for line in disassemble_range(self.machine.memory(), start, start.saturating_add(n)) {
+                    let marker = if line.addr == self.machine.ip() { ">" } else { " " };
+                    println!("{}{}", marker, line);
+                }
+            },
+            "m" => {
+                let start = addr(0)?;
+                let n = count(1, 10)?;
+                for a in start..start.saturating_add(n) {
+                    println!("[{}] = {}", a, self.machine.memory().read(a));
+                }
+            },
+            "i" => {
+                for ix in 0..args.len() {
+                    self.machine.push_input(num(ix)?.unwrap());
+                }
+            },
+            "a" => {
+                let text = line.trim_start()[1..].trim_start().trim_end_matches(['\r', '\n']);
+                for c in text.chars().chain(std::iter::once('\n')) {
+                    self.machine.push_input(c as Mem);
+                }
+            },
+            "o" => {
+                self.output_mode = match args.first() {
+                    Some(&"ascii") => OutputMode::Ascii,
+                    Some(&"num") => OutputMode::Numeric,
+                    _ => return Err(String::from("usage: o ascii|num")),
+                };
+            },
+            "find" => self.find(&args)?,
+            "pin" => {
+                let a = addr(0)?;
+                let val = num(1)?.ok_or("usage: pin ADDR VAL")?;
+                self.pins.pin(a, val);
+                self.pins.apply(&mut self.machine);
+            },
+            "unpin" => self.pins.unpin(addr(0)?),
+            "poke" => {
+                let a = addr(0)?;
+                let val = num(1)?.ok_or("usage: poke ADDR VAL")?;
+                self.machine.poke(a, val);
+            },
+            "save" => {
+                let path = args.first().ok_or("usage: save PATH")?;
+                snapshot::save(&self.machine, Path::new(path)).map_err(|e| e.to_string())?;
+            },
+            "load" => {
+                let path = args.first().ok_or("usage: load PATH")?;
+                self.machine = snapshot::load(Path::new(path)).map_err(|e| e.to_string())?;
+                self.history = History::with_capacity(HISTORY_STEPS);
+                self.print_current();
+            },
+            "h" | "?" => print!("{}", HELP),
+            "q" => return Ok(false),
+            _ => return Err(format!("unknown command '{}', try 'h'", cmd)),
+        }
+        Ok(true)
+    }
+}
+
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: intdbg PROGRAM");
+        std::process::exit(2);
+    });
+    let mut source = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut f| f.read_to_string(&mut source)) {
+        eprintln!("intdbg: {}: {}", path, e);
+        std::process::exit(1);
+    }
+    let mut debugger = Debugger::new(parse_intcode_program(&source));
+    debugger.print_current();
+
+    let stdin = io::stdin();
+    loop {
+        print!("(intdbg) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match debugger.command(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(msg) => println!("error: {}", msg),
+        }
+    }
+}
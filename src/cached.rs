@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use crate::intcode::{decode_instr, to_ptr, Arithmetic, ErrorKind, Event, InputOutput, IntcodeError, Machine, Mem, Memory, Op, Param, Ptr};
+
+/// The longest instruction, in words.
+const MAX_WIDTH: usize = 4;
+
+fn width(op: &Op) -> usize {
+    match op {
+        Op::Add(..) | Op::Mul(..) | Op::LessThan(..) | Op::Equals(..) => 4,
+        Op::JumpIfTrue(..) | Op::JumpIfFalse(..) => 3,
+        Op::In(_) | Op::Out(_) | Op::AdjustRelBase(_) => 2,
+        Op::End => 1,
+    }
+}
+
+/// A machine that decodes the program image once, up front, instead of on
+/// every step. A write drops the decoded instructions it overlaps, which are
+/// then decoded again the next time they run, so self-modifying programs
+/// behave exactly as on `Machine`. Code outside the initial image is never
+/// cached.
+///
+/// The decoded instructions are shared between forks until one of them
+/// modifies its code, like memory pages are.
+#[derive(Clone)]
+pub struct CachedMachine {
+    memory: Memory,
+    decoded: Arc<Vec<Option<Op>>>,
+    ip: Ptr,
+    rel_base: Mem,
+    input: VecDeque<Mem>,
+    halted: bool,
+    arithmetic: Arithmetic,
+}
+
+impl From<Machine> for CachedMachine {
+    fn from(machine: Machine) -> Self {
+        let memory = machine.memory().clone();
+        let decoded = (0..memory.len()).map(|addr| decode_instr(&memory, addr).ok()).collect();
+        CachedMachine {
+            memory,
+            decoded: Arc::new(decoded),
+            ip: machine.ip(),
+            rel_base: machine.rel_base(),
+            input: machine.input_queue().clone(),
+            halted: machine.is_halted(),
+            arithmetic: machine.arithmetic(),
+        }
+    }
+}
+
+impl CachedMachine {
+    pub fn new(program: Vec<Mem>) -> Self {
+        CachedMachine::from(Machine::new(program))
+    }
+
+    pub fn into_machine(self) -> Machine {
+        Machine::from_parts(self.memory, self.ip, self.rel_base, self.input, self.halted)
+            .with_arithmetic(self.arithmetic)
+    }
+
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        CachedMachine { arithmetic, ..self }
+    }
+
+    pub fn ip(&self) -> Ptr {
+        self.ip
+    }
+
+    pub fn rel_base(&self) -> Mem {
+        self.rel_base
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn into_memory(self) -> Vec<Mem> {
+        self.memory.into_vec()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn push_input(&mut self, x: Mem) {
+        self.input.push_back(x)
+    }
+
+    pub fn pending_input(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn fork(&self) -> CachedMachine {
+        self.clone()
+    }
+
+    pub fn poke(&mut self, addr: Ptr, val: Mem) {
+        self.memory.write(addr, val);
+        self.invalidate(addr);
+    }
+
+    pub fn resume(&mut self) -> Result<Event, IntcodeError> {
+        loop {
+            if let Some(event) = self.step()? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Executes a single instruction, with the same results as `Machine::step`.
+    pub fn step(&mut self) -> Result<Option<Event>, IntcodeError> {
+        self.execute().map_err(|kind| self.fault(kind))
+    }
+
+    fn fault(&self, kind: ErrorKind) -> IntcodeError {
+        IntcodeError { kind, ip: self.ip, opcode: self.memory.read(self.ip), rel_base: self.rel_base }
+    }
+
+    fn fetch(&mut self) -> Result<Op, ErrorKind> {
+        if let Some(Some(op)) = self.decoded.get(self.ip) {
+            return Ok(*op);
+        }
+        let op = decode_instr(&self.memory, self.ip)?;
+        if self.ip < self.decoded.len() {
+            Arc::make_mut(&mut self.decoded)[self.ip] = Some(op);
+        }
+        Ok(op)
+    }
+
+    /// Drops the decoded instructions that include `addr`.
+    fn invalidate(&mut self, addr: Ptr) {
+        if addr >= self.decoded.len() + MAX_WIDTH - 1 {
+            return;
+        }
+        let lo = addr.saturating_sub(MAX_WIDTH - 1);
+        let hi = std::cmp::min(addr + 1, self.decoded.len());
+        let covers = |start: Ptr, slot: &Option<Op>| slot.is_some_and(|op| start + width(&op) > addr);
+        if (lo..hi).any(|start| covers(start, &self.decoded[start])) {
+            for (start, slot) in (lo..hi).zip(&mut Arc::make_mut(&mut self.decoded)[lo..hi]) {
+                if covers(start, slot) {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    fn read(&self, param: Param) -> Result<Mem, ErrorKind> {
+        match param {
+            Param::Pos(ptr) => Ok(self.memory.read(ptr)),
+            Param::Imm(val) => Ok(val),
+            Param::Rel(adj) => Ok(self.memory.read(to_ptr(self.arithmetic.add(self.rel_base, adj)?)?)),
+        }
+    }
+
+    fn store(&mut self, param: Param, val: Mem) -> Result<(), ErrorKind> {
+        let addr = match param {
+            Param::Pos(ptr) => ptr,
+            Param::Imm(_) => return Err(ErrorKind::WriteToImmediate),
+            Param::Rel(adj) => to_ptr(self.arithmetic.add(self.rel_base, adj)?)?,
+        };
+        self.memory.store(addr, val)?;
+        self.invalidate(addr);
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<Option<Event>, ErrorKind> {
+        if self.halted {
+            return Ok(Some(Event::Halted));
+        }
+        let ip = self.ip;
+        match self.fetch()? {
+            Op::Add(lhs, rhs, dest) => {
+                let val = self.arithmetic.add(self.read(lhs)?, self.read(rhs)?)?;
+                self.store(dest, val)?;
+                self.ip = ip+4;
+            },
+            Op::Mul(lhs, rhs, dest) => {
+                let val = self.arithmetic.mul(self.read(lhs)?, self.read(rhs)?)?;
+                self.store(dest, val)?;
+                self.ip = ip+4;
+            },
+            Op::In(p) => {
+                match self.input.pop_front() {
+                    Some(x) => self.store(p, x)?,
+                    None => return Ok(Some(Event::NeedInput)),
+                }
+                self.ip = ip+2;
+            },
+            Op::Out(p) => {
+                let val = self.read(p)?;
+                self.ip = ip+2;
+                return Ok(Some(Event::Output(val)));
+            },
+            Op::JumpIfTrue(expr, dest) => {
+                self.ip = if self.read(expr)? != 0 { to_ptr(self.read(dest)?)? } else { ip+3 };
+            },
+            Op::JumpIfFalse(expr, dest) => {
+                self.ip = if self.read(expr)? == 0 { to_ptr(self.read(dest)?)? } else { ip+3 };
+            },
+            Op::LessThan(lhs, rhs, dest) => {
+                let val = (self.read(lhs)? < self.read(rhs)?) as Mem;
+                self.store(dest, val)?;
+                self.ip = ip+4;
+            },
+            Op::Equals(lhs, rhs, dest) => {
+                let val = (self.read(lhs)? == self.read(rhs)?) as Mem;
+                self.store(dest, val)?;
+                self.ip = ip+4;
+            },
+            Op::AdjustRelBase(adjustment) => {
+                self.rel_base = self.arithmetic.add(self.rel_base, self.read(adjustment)?)?;
+                self.ip = ip+2;
+            },
+            Op::End => {
+                self.halted = true;
+                return Ok(Some(Event::Halted));
+            },
+        }
+        Ok(None)
+    }
+}
+
+/// `intcode::run_program` on the cached engine.
+pub fn run_program_cached(
+    memdata: Vec<Mem>,
+    io: &mut dyn InputOutput) -> Result<Vec<Mem>, IntcodeError>
+{
+    let mut machine = CachedMachine::new(memdata);
+    loop {
+        match machine.resume()? {
+            Event::NeedInput => {
+                let x = io.next_input().map_err(|kind| machine.fault(kind))?;
+                machine.push_input(x);
+            },
+            Event::Output(x) => io.next_output(x),
+            Event::Halted => return Ok(machine.into_memory()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::intcode::run_program_splitio;
+
+    struct Split<'a>(&'a mut Vec<Mem>, &'a mut Vec<Mem>);
+
+    impl InputOutput for Split<'_> {
+        fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+            self.0.pop().ok_or(ErrorKind::InputExhausted)
+        }
+
+        fn next_output(&mut self, x: Mem) {
+            self.1.push(x)
+        }
+    }
+
+    #[test]
+    fn test_same_as_interpreter() {
+        let program = crate::io::parse_intcode_program(&include_str!("../data/day09.in").to_string());
+        for mode in [1, 2] {
+            let mut expected = vec![];
+            let expected_mem = run_program_splitio(program.clone(), &mut vec![mode], &mut expected).unwrap();
+            let mut out = vec![];
+            let mem = run_program_cached(program.clone(), &mut Split(&mut vec![mode], &mut out)).unwrap();
+            assert_eq!(out, expected);
+            assert_eq!(mem, expected_mem);
+        }
+
+        let err = run_program_cached(program, &mut Split(&mut vec![], &mut vec![])).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InputExhausted);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // Counts down by rewriting the immediate operand of its own OUT.
+        let program = assemble("\
+        top:    OUT #3
+                ADD [top+1] #-1 [top+1]
+                JT  [top+1] #top
+                HLT").unwrap();
+        let mut machine = CachedMachine::new(program);
+        let mut fork = machine.fork();
+        let mut out = vec![];
+        while let Event::Output(x) = machine.resume().unwrap() {
+            out.push(x);
+        }
+        assert_eq!(out, vec![3, 2, 1]);
+        // The fork still sees the original code.
+        assert_eq!(fork.resume().unwrap(), Event::Output(3));
+
+        // Overwriting the HLT makes it an invalid instruction.
+        let mut machine = CachedMachine::new(vec![99]);
+        machine.poke(0, 42);
+        assert_eq!(machine.resume().unwrap_err().kind, ErrorKind::InvalidOpcode);
+    }
+}
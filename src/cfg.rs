@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::disasm::format_op;
+use crate::intcode::{decode_instr, Mem, Memory, Op, Param, Ptr};
+
+/// How control leaves a basic block.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Exit {
+    /// The next instruction starts another block.
+    Fallthrough(Ptr),
+    Jump(Ptr),
+    Branch { taken: Ptr, not_taken: Ptr },
+    /// A jump whose target is only known at run time. `not_taken` is set
+    /// for conditional jumps.
+    Computed { not_taken: Option<Ptr> },
+    /// The call idiom: the return address is stored at `rel+0` right before
+    /// an unconditional jump to `target`.
+    Call { target: Ptr, ret: Ptr },
+    /// The call idiom with a target only known at run time.
+    ComputedCall { ret: Ptr },
+    /// An unconditional jump to the address stored at `rel+0`.
+    Return,
+    Halt,
+    /// The block runs into a word that isn't an instruction.
+    Invalid,
+}
+
+impl Exit {
+    /// Successors within the program, including call targets.
+    pub fn successors(&self) -> Vec<Ptr> {
+        match *self {
+            Exit::Fallthrough(p) | Exit::Jump(p) => vec![p],
+            Exit::Branch { taken, not_taken } => vec![taken, not_taken],
+            Exit::Computed { not_taken } => not_taken.into_iter().collect(),
+            Exit::Call { target, ret } => vec![target, ret],
+            Exit::ComputedCall { ret } => vec![ret],
+            Exit::Return | Exit::Halt | Exit::Invalid => vec![],
+        }
+    }
+
+    /// Successors within the same function: calls continue at the return
+    /// address.
+    fn local_successors(&self) -> Vec<Ptr> {
+        match *self {
+            Exit::Call { ret, .. } => vec![ret],
+            _ => self.successors(),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Block {
+    pub start: Ptr,
+    /// Addresses of the instructions in the block.
+    pub instrs: Vec<Ptr>,
+    pub exit: Exit,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Function {
+    pub entry: Ptr,
+    /// Start addresses of the blocks reachable from the entry without
+    /// following calls.
+    pub blocks: Vec<Ptr>,
+}
+
+/// The statically reachable part of a program, split into basic blocks.
+pub struct Cfg {
+    pub blocks: BTreeMap<Ptr, Block>,
+    /// The program entry at address 0 first, then every call target.
+    pub functions: Vec<Function>,
+    memory: Memory,
+}
+
+#[derive(Clone,Copy)]
+enum Target {
+    Known(Ptr),
+    /// The address stored at `rel+0`.
+    ReturnSlot,
+    Computed,
+}
+
+/// Where an instruction may go next.
+enum Flow {
+    Next,
+    Halt,
+    Jump(Target),
+    Branch(Target),
+}
+
+fn flow(op: &Op) -> Flow {
+    let (cond, target, jump_if_true) = match *op {
+        Op::JumpIfTrue(c, t) => (c, t, true),
+        Op::JumpIfFalse(c, t) => (c, t, false),
+        Op::End => return Flow::Halt,
+        _ => return Flow::Next,
+    };
+    let target = match target {
+        Param::Imm(t) if t >= 0 => Target::Known(t as Ptr),
+        Param::Rel(0) => Target::ReturnSlot,
+        _ => Target::Computed,
+    };
+    match cond {
+        Param::Imm(v) if (v != 0) == jump_if_true => Flow::Jump(target),
+        Param::Imm(_) => Flow::Next,
+        _ => Flow::Branch(target),
+    }
+}
+
+/// The value an instruction stores at `rel+0`, if it's a constant.
+fn stores_return_address(op: &Op) -> Option<Mem> {
+    match *op {
+        Op::Add(Param::Imm(a), Param::Imm(b), Param::Rel(0)) => a.checked_add(b),
+        Op::Mul(Param::Imm(a), Param::Imm(b), Param::Rel(0)) => a.checked_mul(b),
+        _ => None,
+    }
+}
+
+impl Cfg {
+    pub fn build(program: &[Mem]) -> Cfg {
+        let memory = Memory::new(program.to_vec());
+        let decode = |addr: Ptr| decode_instr(&memory, addr).ok();
+
+        // Find every reachable instruction and the block leaders.
+        let mut instrs: BTreeMap<Ptr, Option<Op>> = BTreeMap::new();
+        let mut leaders = BTreeSet::from([0]);
+        let mut work = vec![0];
+        let mut prev_op: BTreeMap<Ptr, Op> = BTreeMap::new();
+        while let Some(addr) = work.pop() {
+            if instrs.contains_key(&addr) {
+                continue;
+            }
+            let op = decode(addr);
+            instrs.insert(addr, op);
+            let op = match op {
+                Some(op) => op,
+                None => continue,
+            };
+            let next = addr + op.len();
+            let mut visit = |p: Ptr, leader: bool, leaders: &mut BTreeSet<Ptr>| {
+                if leader {
+                    leaders.insert(p);
+                }
+                work.push(p);
+            };
+            match flow(&op) {
+                Flow::Next => {
+                    prev_op.insert(next, op);
+                    visit(next, false, &mut leaders);
+                },
+                Flow::Halt => (),
+                Flow::Jump(target) => {
+                    if let Target::Known(t) = target {
+                        visit(t, true, &mut leaders);
+                    }
+                    // The instruction after a call is where it returns to.
+                    let is_call = prev_op.get(&addr).and_then(stores_return_address) == Some(next as Mem);
+                    if is_call && !matches!(target, Target::ReturnSlot) {
+                        visit(next, true, &mut leaders);
+                    }
+                },
+                Flow::Branch(target) => {
+                    if let Target::Known(t) = target {
+                        visit(t, true, &mut leaders);
+                    }
+                    visit(next, true, &mut leaders);
+                },
+            }
+        }
+
+        // Split the instructions into blocks.
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut addr = start;
+            let mut block = Block { start, instrs: Vec::new(), exit: Exit::Invalid };
+            while let Some(&Some(op)) = instrs.get(&addr) {
+                block.instrs.push(addr);
+                let next = addr + op.len();
+                block.exit = match flow(&op) {
+                    Flow::Next if leaders.contains(&next) => Exit::Fallthrough(next),
+                    Flow::Next => {
+                        addr = next;
+                        continue;
+                    },
+                    Flow::Halt => Exit::Halt,
+                    Flow::Jump(target) => {
+                        let prev = block.instrs.len().checked_sub(2)
+                            .and_then(|ix| instrs[&block.instrs[ix]]);
+                        let is_call = prev.as_ref().and_then(stores_return_address) == Some(next as Mem)
+                            && instrs.contains_key(&next);
+                        match target {
+                            Target::Known(target) if is_call => Exit::Call { target, ret: next },
+                            Target::Known(target) => Exit::Jump(target),
+                            Target::Computed if is_call => Exit::ComputedCall { ret: next },
+                            Target::Computed => Exit::Computed { not_taken: None },
+                            Target::ReturnSlot => Exit::Return,
+                        }
+                    },
+                    Flow::Branch(Target::Known(taken)) => Exit::Branch { taken, not_taken: next },
+                    Flow::Branch(_) => Exit::Computed { not_taken: Some(next) },
+                };
+                break;
+            }
+            blocks.insert(start, block);
+        }
+
+        let mut entries = vec![0];
+        for block in blocks.values() {
+            if let Exit::Call { target, .. } = block.exit {
+                if !entries.contains(&target) {
+                    entries.push(target);
+                }
+            }
+        }
+        entries[1..].sort();
+        let functions = entries.into_iter().map(|entry| {
+            let mut seen = BTreeSet::new();
+            let mut work = vec![entry];
+            while let Some(b) = work.pop() {
+                if seen.insert(b) {
+                    if let Some(block) = blocks.get(&b) {
+                        work.extend(block.exit.local_successors());
+                    }
+                }
+            }
+            Function { entry, blocks: seen.into_iter().collect() }
+        }).collect();
+
+        Cfg { blocks, functions, memory }
+    }
+
+    /// Blocks ending in a jump whose target isn't known statically.
+    pub fn computed_jumps(&self) -> Vec<Ptr> {
+        self.blocks.values()
+            .filter(|b| matches!(b.exit, Exit::Computed { .. } | Exit::ComputedCall { .. }))
+            .map(|b| b.start)
+            .collect()
+    }
+
+    /// Graphviz source with the disassembly in each node. Function entries
+    /// are labelled, computed jumps are red, and call edges dashed.
+    pub fn to_dot(&self) -> String {
+        let entries: BTreeSet<Ptr> = self.functions.iter().map(|f| f.entry).collect();
+        let mut out = String::new();
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if entries.contains(&block.start) {
+                write!(label, "fn_{}:\\l", block.start).unwrap();
+            }
+            for &addr in &block.instrs {
+                let op = decode_instr(&self.memory, addr).unwrap();
+                write!(label, "{:>5}: {}\\l", addr, format_op(&op)).unwrap();
+            }
+            match block.exit {
+                Exit::Return => label.push_str("return\\l"),
+                Exit::Invalid => label.push_str("invalid instruction\\l"),
+                _ => (),
+            }
+            let color = match block.exit {
+                Exit::Computed { .. } | Exit::ComputedCall { .. } => ", color=red",
+                _ => "",
+            };
+            writeln!(out, "    b{} [label=\"{}\"{}];", block.start, label.replace('"', "\\\""), color).unwrap();
+        }
+        for block in self.blocks.values() {
+            let from = block.start;
+            match block.exit {
+                Exit::Fallthrough(to) | Exit::Jump(to) => writeln!(out, "    b{} -> b{};", from, to).unwrap(),
+                Exit::Branch { taken, not_taken } => {
+                    writeln!(out, "    b{} -> b{} [label=\"taken\"];", from, taken).unwrap();
+                    writeln!(out, "    b{} -> b{};", from, not_taken).unwrap();
+                },
+                Exit::Computed { not_taken: Some(to) } | Exit::ComputedCall { ret: to } =>
+                    writeln!(out, "    b{} -> b{};", from, to).unwrap(),
+                Exit::Call { target, ret } => {
+                    writeln!(out, "    b{} -> b{} [style=dashed, label=\"call\"];", from, target).unwrap();
+                    writeln!(out, "    b{} -> b{};", from, ret).unwrap();
+                },
+                Exit::Computed { not_taken: None } | Exit::Return | Exit::Halt | Exit::Invalid => (),
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_blocks() {
+        let program = assemble("\
+                IN  [n]
+        loop:   JF  [n] #done
+                ADD [n] #-1 [n]
+                JT  #1 #loop
+        done:   JT  [n] [n]
+                HLT
+        n:      DATA 0").unwrap();
+        let cfg = Cfg::build(&program);
+        let starts: Vec<Ptr> = cfg.blocks.keys().cloned().collect();
+        assert_eq!(starts, vec![0, 2, 5, 12, 15]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Fallthrough(2));
+        assert_eq!(cfg.blocks[&2].exit, Exit::Branch { taken: 12, not_taken: 5 });
+        assert_eq!(cfg.blocks[&5].instrs, vec![5, 9]);
+        assert_eq!(cfg.blocks[&5].exit, Exit::Jump(2));
+        assert_eq!(cfg.blocks[&12].exit, Exit::Computed { not_taken: Some(15) });
+        assert_eq!(cfg.blocks[&15].exit, Exit::Halt);
+        assert_eq!(cfg.computed_jumps(), vec![12]);
+        assert_eq!(cfg.functions.len(), 1);
+    }
+
+    #[test]
+    fn test_functions() {
+        let program = assemble("\
+                ARB #100
+                ADD #after1 #0 rel+0
+                JT  #1 #double
+        after1: ADD #0 #after2 rel+0
+                JF  #0 #double
+        after2: HLT
+        double: ARB #1
+                MUL rel+0 #2 rel+0
+                ARB #-1
+                JT  #1 rel+0").unwrap();
+        let cfg = Cfg::build(&program);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Call { target: 17, ret: 9 });
+        assert_eq!(cfg.blocks[&9].exit, Exit::Call { target: 17, ret: 16 });
+        assert_eq!(cfg.blocks[&17].exit, Exit::Return);
+        assert_eq!(cfg.functions, vec![
+            Function { entry: 0, blocks: vec![0, 9, 16] },
+            Function { entry: 17, blocks: vec![17] },
+        ]);
+
+        let dot = cfg.to_dot();
+        assert!(dot.contains("b0 -> b17 [style=dashed, label=\"call\"];"));
+        assert!(dot.contains("fn_17:\\l   17: ARB  #1\\l"));
+
+        // A return address that overflows isn't a call.
+        let cfg = Cfg::build(&[21101, Mem::MAX, 1, 0, 1105, 1, 7, 99]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Jump(7));
+    }
+
+    #[test]
+    fn test_day19() {
+        let program = crate::io::parse_intcode_program(&include_str!("../data/day19.in").to_string());
+        let cfg = Cfg::build(&program);
+        let entries: Vec<Ptr> = cfg.functions.iter().map(|f| f.entry).collect();
+        assert_eq!(entries, vec![0, 225, 259, 282, 303]);
+        // JT #1 [109], after storing the return address 195.
+        assert_eq!(cfg.blocks[&148].exit, Exit::ComputedCall { ret: 195 });
+        assert!(cfg.blocks.values().all(|b| b.exit != Exit::Invalid));
+    }
+}
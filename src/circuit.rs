@@ -0,0 +1,197 @@
+use crate::intcode::{Event, IntcodeError, Machine, Mem};
+use crate::permutation::Permutations;
+
+pub type NodeId = usize;
+pub type EdgeId = usize;
+
+struct Node {
+    machine: Machine,
+    outputs: Vec<EdgeId>,
+}
+
+struct Edge {
+    from: NodeId,
+    to: Option<NodeId>,
+    values: Vec<Mem>,
+}
+
+/// A set of intcode machines wired together by output -> input edges. Every
+/// output of a machine is copied onto all of its outgoing edges, and a machine
+/// with several incoming edges reads their values in the order they arrive.
+/// Edges may form cycles.
+#[derive(Default)]
+pub struct Circuit {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Circuit { nodes: Vec::new(), edges: Vec::new() }
+    }
+
+    pub fn add_machine(&mut self, machine: Machine) -> NodeId {
+        self.nodes.push(Node { machine, outputs: Vec::new() });
+        self.nodes.len() - 1
+    }
+
+    pub fn add_program(&mut self, program: Vec<Mem>) -> NodeId {
+        self.add_machine(Machine::new(program))
+    }
+
+    /// Queues an initial input, such as a phase setting.
+    pub fn seed(&mut self, node: NodeId, value: Mem) {
+        self.nodes[node].machine.push_input(value)
+    }
+
+    pub fn connect(&mut self, from: NodeId, to: NodeId) -> EdgeId {
+        self.add_edge(from, Some(to))
+    }
+
+    /// An edge that records the outputs of `from` without feeding any machine.
+    pub fn tap(&mut self, from: NodeId) -> EdgeId {
+        self.add_edge(from, None)
+    }
+
+    fn add_edge(&mut self, from: NodeId, to: Option<NodeId>) -> EdgeId {
+        self.edges.push(Edge { from, to, values: Vec::new() });
+        let id = self.edges.len() - 1;
+        self.nodes[from].outputs.push(id);
+        id
+    }
+
+    pub fn machine(&self, node: NodeId) -> &Machine {
+        &self.nodes[node].machine
+    }
+
+    /// Every value sent along the edge so far.
+    pub fn values(&self, edge: EdgeId) -> &[Mem] {
+        &self.edges[edge].values
+    }
+
+    pub fn last_value(&self, edge: EdgeId) -> Option<Mem> {
+        self.edges[edge].values.last().cloned()
+    }
+
+    pub fn source(&self, edge: EdgeId) -> NodeId {
+        self.edges[edge].from
+    }
+
+    /// Runs the machines round-robin until every one of them has halted or
+    /// is waiting for input that no other machine will provide.
+    pub fn run(&mut self) -> Result<(), IntcodeError> {
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for ix in 0..self.nodes.len() {
+                while let Event::Output(x) = self.nodes[ix].machine.resume()? {
+                    progress = true;
+                    for e in 0..self.nodes[ix].outputs.len() {
+                        let edge = &mut self.edges[self.nodes[ix].outputs[e]];
+                        edge.values.push(x);
+                        if let Some(to) = edge.to {
+                            self.nodes[to].machine.push_input(x);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn all_halted(&self) -> bool {
+        self.nodes.iter().all(|n| n.machine.is_halted())
+    }
+}
+
+type Best = Option<(Vec<Mem>, Mem)>;
+
+/// Evaluates every permutation of `phases` on a pool of threads and returns
+/// the one with the highest score.
+pub fn best_permutation<F, E>(phases: Vec<Mem>, evaluate: F) -> Result<Best, E>
+    where F: Fn(&[Mem]) -> Result<Mem, E> + Sync,
+          E: Send
+{
+    let permutations: Vec<Vec<Mem>> = Permutations::new(phases).collect();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = permutations.len().div_ceil(threads).max(1);
+
+    let results: Vec<Result<Best, E>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = permutations
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let evaluate = &evaluate;
+                scope.spawn(move || {
+                    let mut best: Best = None;
+                    for phases in chunk {
+                        let score = evaluate(phases)?;
+                        if best.as_ref().is_none_or(|(_, s)| score > *s) {
+                            best = Some((phases.clone(), score));
+                        }
+                    }
+                    Ok(best)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().expect("evaluation thread panicked")).collect()
+    });
+
+    let mut best: Best = None;
+    for result in results {
+        if let Some((phases, score)) = result? {
+            if best.as_ref().is_none_or(|(_, s)| score > *s) {
+                best = Some((phases, score));
+            }
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IN [9]; ADD [9] #1 [9]; OUT [9]; HLT
+    const INCREMENT: [Mem; 10] = [3, 9, 1001, 9, 1, 9, 4, 9, 99, 0];
+
+    #[test]
+    fn test_fan_out_fan_in() {
+        let mut circuit = Circuit::new();
+        let src = circuit.add_program(INCREMENT.to_vec());
+        let left = circuit.add_program(INCREMENT.to_vec());
+        let right = circuit.add_program(INCREMENT.to_vec());
+        // IN [13]; IN [14]; MUL [13] [14] [13]; OUT [13]; HLT
+        let join = circuit.add_program(vec![3, 13, 3, 14, 2, 13, 14, 13, 4, 13, 99, 0, 0, 0, 0]);
+        circuit.connect(src, left);
+        circuit.connect(src, right);
+        let l = circuit.connect(left, join);
+        circuit.connect(right, join);
+        let out = circuit.tap(join);
+        circuit.seed(src, 1);
+        circuit.run().unwrap();
+        assert_eq!(circuit.values(l), &[3]);
+        assert_eq!(circuit.values(out), &[9]);
+        assert!(circuit.all_halted());
+    }
+
+    #[test]
+    fn test_quiescence() {
+        let mut circuit = Circuit::new();
+        let a = circuit.add_program(INCREMENT.to_vec());
+        let b = circuit.add_program(INCREMENT.to_vec());
+        let e = circuit.connect(a, b);
+        circuit.run().unwrap();
+        assert!(circuit.values(e).is_empty());
+        assert!(!circuit.all_halted());
+        circuit.seed(a, 10);
+        circuit.run().unwrap();
+        assert_eq!(circuit.last_value(e), Some(11));
+        assert!(circuit.machine(b).is_halted());
+    }
+
+    #[test]
+    fn test_best_permutation() {
+        let best = best_permutation(vec![1, 2, 3], |p| Ok::<Mem, ()>(p[0] * 100 + p[1] * 10 - p[2])).unwrap();
+        assert_eq!(best, Some((vec![3, 2, 1], 319)));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::intcode::{decode_param, to_ptr, ErrorKind, Event, IntcodeError, Machine, Mem, Memory, Param, Ptr};
+
+/// What an extension opcode does with each of its parameters. Parameter
+/// modes work as for the built-in instructions.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Role {
+    Read,
+    Write,
+}
+
+/// What happens after an extension instruction has run.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Action {
+    /// Go on with the next instruction.
+    Next,
+    Jump(Ptr),
+    /// Go on with the next instruction, producing an output.
+    Output(Mem),
+    /// Go on with the next instruction, but stop and report a trap first.
+    Trap,
+}
+
+/// The operands of an executing extension instruction.
+pub struct Invocation<'m> {
+    pub ip: Ptr,
+    pub rel_base: Mem,
+    /// Values of the `Read` parameters, in order.
+    pub inputs: Vec<Mem>,
+    /// Values to store through the `Write` parameters, in order. They start
+    /// out as what the cells already hold.
+    pub outputs: Vec<Mem>,
+    memory: &'m Memory,
+}
+
+impl Invocation<'_> {
+    /// Memory as it was before the instruction, e.g. for reading a buffer
+    /// whose address was passed as a parameter.
+    pub fn memory(&self) -> &Memory {
+        self.memory
+    }
+}
+
+type Callback<'a> = Box<dyn FnMut(&mut Invocation) -> Result<Action, ErrorKind> + 'a>;
+
+struct Extension<'a> {
+    roles: Vec<Role>,
+    callback: Callback<'a>,
+}
+
+/// Why `Dialect::resume` stopped.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Stop {
+    Event(Event),
+    /// An extension returned `Action::Trap` at `ip`.
+    Trap(Ptr),
+}
+
+/// Extra opcodes on top of the built-in instruction set. A machine run
+/// through a dialect executes registered opcodes with their callbacks, and
+/// everything else as usual.
+#[derive(Default)]
+pub struct Dialect<'a> {
+    extensions: Vec<Option<Extension<'a>>>,
+}
+
+const BUILTIN: [Mem; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+
+impl<'a> Dialect<'a> {
+    pub fn new() -> Self {
+        Dialect::default()
+    }
+
+    /// Adds an opcode (without parameter modes) taking parameters with the
+    /// given roles. Panics if the opcode is built in, already registered or
+    /// not below 100.
+    pub fn register<F>(&mut self, opcode: Mem, roles: &[Role], callback: F) -> &mut Self
+        where F: FnMut(&mut Invocation) -> Result<Action, ErrorKind> + 'a
+    {
+        assert!((0..100).contains(&opcode) && !BUILTIN.contains(&opcode), "can't register opcode {}", opcode);
+        let ix = opcode as usize;
+        if self.extensions.len() <= ix {
+            self.extensions.resize_with(ix + 1, || None);
+        }
+        assert!(self.extensions[ix].is_none(), "opcode {} is already registered", opcode);
+        self.extensions[ix] = Some(Extension { roles: roles.to_vec(), callback: Box::new(callback) });
+        self
+    }
+
+    fn lookup(&mut self, opcode: Mem) -> Option<&mut Extension<'a>> {
+        if opcode < 0 {
+            return None;
+        }
+        self.extensions.get_mut((opcode % 100) as usize)?.as_mut()
+    }
+
+    /// Like `Machine::step`, with the extensions.
+    pub fn step(&mut self, machine: &mut Machine) -> Result<Option<Stop>, IntcodeError> {
+        if machine.is_halted() {
+            return Ok(Some(Stop::Event(Event::Halted)));
+        }
+        let ip = machine.ip();
+        let opcode = machine.memory().read(ip);
+        match self.lookup(opcode) {
+            Some(ext) => execute(ext, machine, opcode)
+                .map_err(|kind| IntcodeError { kind, ip, opcode, rel_base: machine.rel_base() }),
+            None => Ok(machine.step()?.map(Stop::Event)),
+        }
+    }
+
+    /// Like `Machine::resume`, with the extensions. Also stops at traps.
+    pub fn resume(&mut self, machine: &mut Machine) -> Result<Stop, IntcodeError> {
+        loop {
+            if let Some(stop) = self.step(machine)? {
+                return Ok(stop);
+            }
+        }
+    }
+}
+
+fn execute(ext: &mut Extension, machine: &mut Machine, opcode: Mem) -> Result<Option<Stop>, ErrorKind> {
+    let (ip, rel_base) = (machine.ip(), machine.rel_base());
+    let memory = machine.memory();
+    let mut inputs = Vec::new();
+    let mut targets = Vec::new();
+    for (ix, role) in ext.roles.iter().enumerate() {
+        let param = decode_param(memory, ip + 1 + ix, opcode, ix as u32)?;
+        match role {
+            Role::Read => inputs.push(memory.read_param(&param, rel_base, machine.arithmetic())?),
+            Role::Write => targets.push(match param {
+                Param::Pos(ptr) => ptr,
+                Param::Imm(_) => return Err(ErrorKind::WriteToImmediate),
+                Param::Rel(adj) => to_ptr(machine.arithmetic().add(rel_base, adj)?)?,
+            }),
+        }
+    }
+    let outputs = targets.iter().map(|&addr| memory.read(addr)).collect();
+    let mut invocation = Invocation { ip, rel_base, inputs, outputs, memory };
+    let action = (ext.callback)(&mut invocation)?;
+    for (addr, val) in targets.into_iter().zip(invocation.outputs) {
+        machine.store(addr, val)?;
+    }
+    let next = ip + 1 + ext.roles.len();
+    let (new_ip, stop) = match action {
+        Action::Next => (next, None),
+        Action::Jump(target) => (target, None),
+        Action::Output(x) => (next, Some(Stop::Event(Event::Output(x)))),
+        Action::Trap => (next, Some(Stop::Trap(ip))),
+    };
+    machine.set_ip(new_ip);
+    Ok(stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions() {
+        let program = vec![
+            1101, 20, 22, 100,  // ADD #20 #22 [100]
+            50, 100,            // DBG [100]
+            151, 7, 101,        // SQR #7 [101]
+            52,                 // TRAP
+            4, 101,             // OUT [101]
+            99,
+        ];
+        let mut printed = Vec::new();
+        let mut machine = Machine::new(program.clone());
+        {
+            let mut dialect = Dialect::new();
+            dialect
+                .register(50, &[Role::Read], |inv| {
+                    printed.push(inv.inputs[0]);
+                    Ok(Action::Next)
+                })
+                .register(51, &[Role::Read, Role::Write], |inv| {
+                    inv.outputs[0] = inv.inputs[0] * inv.inputs[0];
+                    Ok(Action::Next)
+                })
+                .register(52, &[], |_| Ok(Action::Trap));
+            assert_eq!(dialect.resume(&mut machine), Ok(Stop::Trap(9)));
+            assert_eq!(machine.memory().read(101), 49);
+            assert_eq!(dialect.resume(&mut machine), Ok(Stop::Event(Event::Output(49))));
+            assert_eq!(dialect.resume(&mut machine), Ok(Stop::Event(Event::Halted)));
+        }
+        assert_eq!(printed, vec![42]);
+
+        // Without the extensions, the same program faults.
+        let err = Machine::new(program).resume().unwrap_err();
+        assert_eq!((err.kind, err.ip), (ErrorKind::InvalidOpcode, 4));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_builtin_opcode() {
+        Dialect::new().register(3, &[Role::Write], |_| Ok(Action::Next));
+    }
+}
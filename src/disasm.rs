@@ -0,0 +1,116 @@
+use crate::intcode::{decode_instr, Mem, Memory, Op, Param, Ptr};
+
+pub struct Line {
+    pub addr: Ptr,
+    pub words: Vec<Mem>,
+    pub text: String,
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let words: Vec<String> = self.words.iter().map(|w| w.to_string()).collect();
+        write!(f, "{:>5}: {:<32} ; {}", self.addr, self.text, words.join(","))
+    }
+}
+
+pub(crate) fn mnemonic(op: &Op) -> &'static str {
+    match op {
+        Op::Add(..) => "ADD",
+        Op::Mul(..) => "MUL",
+        Op::In(_) => "IN",
+        Op::Out(_) => "OUT",
+        Op::JumpIfTrue(..) => "JT",
+        Op::JumpIfFalse(..) => "JF",
+        Op::LessThan(..) => "LT",
+        Op::Equals(..) => "EQ",
+        Op::AdjustRelBase(_) => "ARB",
+        Op::End => "HLT",
+    }
+}
+
+pub(crate) fn format_param(param: &Param) -> String {
+    match *param {
+        Param::Pos(ptr) => format!("[{}]", ptr),
+        Param::Imm(val) => format!("#{}", val),
+        Param::Rel(adj) if adj < 0 => format!("rel{}", adj),
+        Param::Rel(adj) => format!("rel+{}", adj),
+    }
+}
+
+pub(crate) fn format_op(op: &Op) -> String {
+    let mut text = format!("{:<4}", mnemonic(op));
+    for p in op.params() {
+        text.push(' ');
+        text.push_str(&format_param(p));
+    }
+    text.trim_end().to_string()
+}
+
+/// Disassembles the word at `addr`. Anything that does not decode to an
+/// instruction in its canonical encoding, or that would extend past `end`,
+/// is shown as a single `DATA` word.
+pub fn disassemble_at(mem: &Memory, addr: Ptr, end: Ptr) -> Line {
+    if let Ok(op) = decode_instr(mem, addr) {
+        if op.opcode() == mem.read(addr) && addr + op.len() <= end {
+            let words = (addr..addr + op.len()).map(|p| mem.read(p)).collect();
+            return Line { addr, words, text: format_op(&op) };
+        }
+    }
+    let word = mem.read(addr);
+    Line { addr, words: vec![word], text: format!("DATA {}", word) }
+}
+
+pub fn disassemble_range(mem: &Memory, start: Ptr, end: Ptr) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        let line = disassemble_at(mem, addr, end);
+        addr += line.words.len();
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn disassemble(program: &[Mem]) -> Vec<Line> {
+    let mem = Memory::new(program.to_vec());
+    disassemble_range(&mem, 0, program.len())
+}
+
+pub fn listing(program: &[Mem]) -> String {
+    let mut ret = String::new();
+    for line in disassemble(program) {
+        ret.push_str(&line.to_string());
+        ret.push('\n');
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let lines = disassemble(&[109, 1, 204, -1, 1001, 100, 1, 100, 11105, -1, -2, 99, 7]);
+        let texts: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, vec![
+            "ARB  #1",
+            "OUT  rel-1",
+            "ADD  [100] #1 [100]",
+            "DATA 11105",
+            "DATA -1",
+            "DATA -2",
+            "HLT",
+            "DATA 7",
+        ]);
+        assert_eq!(lines[2].addr, 4);
+        assert_eq!(lines[2].words, vec![1001, 100, 1, 100]);
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        let lines = disassemble(&[1101, 1, 2]);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text, "DATA 1101");
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use crate::intcode::{Machine, MemWrite, Op, Ptr, TraceStep, Tracer};
+use crate::trace::Record;
+
+/// An undo log of executed instructions. Trace a machine with it, and the
+/// machine can later be stepped backwards. Stepping back only works while
+/// the history and the machine agree, so nothing but traced execution should
+/// change the machine in between (pokes, for example, are not undone).
+#[derive(Default)]
+pub struct History {
+    records: VecDeque<Record>,
+    capacity: Option<usize>,
+    /// Step index of the oldest record.
+    first: u64,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Keeps only the last `capacity` steps.
+    pub fn with_capacity(capacity: usize) -> Self {
+        History { capacity: Some(capacity), ..History::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Number of steps executed, including those no longer kept.
+    pub fn steps(&self) -> u64 {
+        self.first + self.records.len() as u64
+    }
+
+    /// Oldest record first.
+    pub fn records(&self) -> impl Iterator<Item=&Record> {
+        self.records.iter()
+    }
+
+    /// Undoes the last instruction and returns it.
+    pub fn step_back(&mut self, machine: &mut Machine) -> Option<Record> {
+        let record = self.records.pop_back()?;
+        if let Some(w) = record.write {
+            machine.poke(w.addr, w.old);
+        }
+        let consumed = match (record.op, record.write) {
+            (Op::In(_), Some(w)) => Some(w.new),
+            _ => None,
+        };
+        machine.rewind(record.ip, record.rel_base, consumed);
+        Some(record)
+    }
+
+    /// Steps back until the machine is about to execute the instruction at
+    /// `addr` again, going back at least one step. Returns the number of
+    /// steps undone, or None, leaving the machine alone, if the history
+    /// doesn't reach back to such a point.
+    pub fn run_back_to(&mut self, machine: &mut Machine, addr: Ptr) -> Option<usize> {
+        let pos = self.records.iter().rposition(|r| r.ip == addr)?;
+        let count = self.records.len() - pos;
+        for _ in 0..count {
+            self.step_back(machine);
+        }
+        Some(count)
+    }
+
+    /// The step index and details of the most recent write to `addr`.
+    pub fn last_write(&self, addr: Ptr) -> Option<(u64, &Record)> {
+        self.writes_to(addr).last()
+    }
+
+    /// Every kept write to `addr`, oldest first.
+    pub fn writes_to(&self, addr: Ptr) -> impl Iterator<Item=(u64, &Record)> {
+        self.records.iter()
+            .enumerate()
+            .filter(move |(_, r)| r.write.is_some_and(|w: MemWrite| w.addr == addr))
+            .map(move |(ix, r)| (self.first + ix as u64, r))
+    }
+}
+
+impl Tracer for History {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.capacity == Some(0) {
+            self.first += 1;
+            return;
+        }
+        if Some(self.records.len()) == self.capacity {
+            self.records.pop_front();
+            self.first += 1;
+        }
+        self.records.push_back(Record::from(step));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::intcode::Event;
+
+    #[test]
+    fn test_step_back() {
+        let program = assemble("\
+                IN  [n]
+        top:    ADD [n] #-1 [n]
+                ARB #1
+                JT  [n] #top
+                OUT [n]
+                HLT
+        n:      DATA 0").unwrap();
+        let mut machine = Machine::new(program.clone());
+        let mut history = History::new();
+        machine.push_input(3);
+        assert_eq!(machine.resume_traced(&mut history).unwrap(), Event::Output(0));
+        assert_eq!(machine.resume_traced(&mut history).unwrap(), Event::Halted);
+        assert_eq!(history.steps(), 1 + 3 * 3 + 2);
+
+        let (step, record) = history.last_write(14).unwrap();
+        assert_eq!(step, 7);
+        assert_eq!(record.write, Some(MemWrite { addr: 14, old: 1, new: 0 }));
+        assert_eq!(history.writes_to(14).count(), 4);
+
+        assert_eq!(history.step_back(&mut machine).unwrap().op, Op::End);
+        assert!(!machine.is_halted());
+        assert_eq!(history.run_back_to(&mut machine, 2), Some(4));
+        assert_eq!((machine.ip(), machine.rel_base()), (2, 2));
+        assert_eq!(machine.memory().read(14), 1);
+        assert_eq!(history.run_back_to(&mut machine, 99), None);
+
+        // All the way back, the input is waiting to be read again.
+        while history.step_back(&mut machine).is_some() {}
+        assert_eq!(machine.ip(), 0);
+        assert_eq!(machine.pending_input(), 1);
+        assert_eq!(machine.clone().into_memory(), program);
+        assert_eq!(machine.resume().unwrap(), Event::Output(0));
+    }
+
+    #[test]
+    fn test_capacity() {
+        // ADD [5] #1 [5]; JT #1 #0
+        let mut machine = Machine::new(vec![1001, 5, 1, 5, 1105, 1, 0]);
+        let mut history = History::with_capacity(3);
+        for _ in 0..10 {
+            machine.step_traced(&mut history).unwrap();
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.steps(), 10);
+        assert_eq!(history.last_write(5).map(|(step, _)| step), Some(8));
+        while history.step_back(&mut machine).is_some() {}
+        assert_eq!(machine.ip(), 4);
+        assert_eq!(machine.memory().read(5), 5);
+    }
+}
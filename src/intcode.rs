@@ -1,52 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 pub type Mem = i64;
 pub type Ptr = usize;
 
+/// Addresses below this limit (or below the end of the program image, if that
+/// is larger) are stored densely; anything beyond goes into a sparse map, so a
+/// single write to a huge address doesn't allocate everything below it.
+pub(crate) const DENSE_LIMIT: Ptr = 1 << 20;
+
+/// The most cells a program may write into the sparse map. Writing to yet
+/// another address beyond the dense region is an error.
+pub(crate) const FAR_LIMIT: usize = 1 << 20;
+
+/// The dense region is split into reference-counted pages, which are copied on
+/// the first write after a clone. Cloning memory is therefore cheap, and forked
+/// machines share every page neither of them has modified.
+const PAGE_SIZE: usize = 256;
+
+type Page = [Mem; PAGE_SIZE];
+
+#[derive(Clone)]
 pub struct Memory {
-    memory: Vec<Mem>
+    pages: Vec<Arc<Page>>,
+    len: usize,
+    dense_limit: Ptr,
+    far: Arc<HashMap<Ptr, Mem>>,
+}
+
+pub(crate) fn to_ptr(addr: Mem) -> Result<Ptr, ErrorKind> {
+    if addr < 0 {
+        Err(ErrorKind::NegativeAddress(addr))
+    } else {
+        Ok(addr as Ptr)
+    }
 }
 
 impl Memory {
     pub fn new(memory: Vec<Mem>) -> Self {
-        Memory {memory}
+        let dense_limit = std::cmp::max(DENSE_LIMIT, memory.len());
+        let pages = memory
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                let mut page = [0; PAGE_SIZE];
+                page[..chunk.len()].copy_from_slice(chunk);
+                Arc::new(page)
+            })
+            .collect();
+        Memory {pages, len: memory.len(), dense_limit, far: Arc::new(HashMap::new())}
+    }
+
+    /// Cells in the dense region, in address order.
+    pub(crate) fn dense(&self) -> impl Iterator<Item=Mem> + '_ {
+        self.pages.iter().flat_map(|page| page.iter().cloned()).take(self.len)
+    }
+
+    /// Cells stored in the sparse region, ordered by address.
+    pub(crate) fn far_cells(&self) -> Vec<(Ptr, Mem)> {
+        let mut cells: Vec<(Ptr, Mem)> = self.far.iter().map(|(p, v)| (*p, *v)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    /// The dense part of memory, i.e. the program image and anything written
+    /// close to it. Cells in the sparse region are not included.
+    pub fn into_vec(self) -> Vec<Mem> {
+        self.dense().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
     }
 
-    fn read(&self, ptr: Ptr) -> Mem {
-        self.memory.get(ptr).cloned().unwrap_or(0)
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
-    fn write(&mut self, ptr: Ptr, val: Mem) {
-        if ptr >= self.memory.len() {
-            self.memory.resize(ptr+1, 0);
+    pub fn read(&self, ptr: Ptr) -> Mem {
+        if ptr < self.len {
+            self.pages[ptr / PAGE_SIZE][ptr % PAGE_SIZE]
+        } else if ptr < self.dense_limit {
+            0
+        } else {
+            self.far.get(&ptr).cloned().unwrap_or(0)
         }
-        self.memory[ptr] = val;
     }
 
-    fn read_param(&self, param: &Param, rel_base: Mem) -> Mem {
-        match *param {
-            Param::Pos(ptr) => self.read(ptr),
-            Param::Imm(val) => val,
-            Param::Rel(adj) => self.read((rel_base+adj) as Ptr),
+    pub(crate) fn write(&mut self, ptr: Ptr, val: Mem) {
+        if ptr >= self.dense_limit {
+            Arc::make_mut(&mut self.far).insert(ptr, val);
+            return;
+        }
+        if ptr >= self.len {
+            let pages_needed = ptr / PAGE_SIZE + 1;
+            while self.pages.len() < pages_needed {
+                self.pages.push(Arc::new([0; PAGE_SIZE]));
+            }
+            self.len = ptr + 1;
         }
+        Arc::make_mut(&mut self.pages[ptr / PAGE_SIZE])[ptr % PAGE_SIZE] = val;
     }
 
-    fn write_param(&mut self, param: &Param, value: Mem, rel_base: Mem) -> Result<(), String> {
+    /// A write made by the program, which fails rather than let the sparse
+    /// map grow beyond `FAR_LIMIT` cells.
+    pub(crate) fn store(&mut self, ptr: Ptr, val: Mem) -> Result<(), ErrorKind> {
+        if ptr >= self.dense_limit && self.far.len() >= FAR_LIMIT && !self.far.contains_key(&ptr) {
+            return Err(ErrorKind::MemoryLimit);
+        }
+        self.write(ptr, val);
+        Ok(())
+    }
+
+    pub(crate) fn read_param(&self, param: &Param, rel_base: Mem, arith: Arithmetic) -> Result<Mem, ErrorKind> {
         match *param {
-            Param::Pos(ptr) => Ok(self.write(ptr, value)),
-            Param::Imm(_) => Err(String::from("writing to immediate")),
-            Param::Rel(adj) => Ok(self.write((rel_base+adj) as Ptr, value)),
+            Param::Pos(ptr) => Ok(self.read(ptr)),
+            Param::Imm(val) => Ok(val),
+            Param::Rel(adj) => Ok(self.read(to_ptr(arith.add(rel_base, adj)?)?)),
         }
     }
+
+    fn write_param(&mut self, param: &Param, value: Mem, rel_base: Mem, arith: Arithmetic) -> Result<MemWrite, ErrorKind> {
+        let addr = match *param {
+            Param::Pos(ptr) => ptr,
+            Param::Imm(_) => return Err(ErrorKind::WriteToImmediate),
+            Param::Rel(adj) => to_ptr(arith.add(rel_base, adj)?)?,
+        };
+        let old = self.read(addr);
+        self.store(addr, value)?;
+        Ok(MemWrite { addr, old, new: value })
+    }
 }
 
-#[derive(Debug,PartialEq)]
-enum Param {
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Param {
     Pos(Ptr),
     Imm(Mem),
     Rel(Mem),
 }
 
-#[derive(Debug,PartialEq)]
-enum Op {
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Op {
     Add(Param, Param, Param),
     Mul(Param, Param, Param),
     In(Param),
@@ -59,13 +153,135 @@ enum Op {
     End,
 }
 
+impl Param {
+    fn mode(&self) -> Mem {
+        match self {
+            Param::Pos(_) => 0,
+            Param::Imm(_) => 1,
+            Param::Rel(_) => 2,
+        }
+    }
+}
+
+impl Op {
+    pub(crate) fn params(&self) -> Vec<&Param> {
+        match self {
+            Op::Add(p0, p1, p2) | Op::Mul(p0, p1, p2) |
+            Op::LessThan(p0, p1, p2) | Op::Equals(p0, p1, p2) => vec![p0, p1, p2],
+            Op::JumpIfTrue(p0, p1) | Op::JumpIfFalse(p0, p1) => vec![p0, p1],
+            Op::In(p) | Op::Out(p) | Op::AdjustRelBase(p) => vec![p],
+            Op::End => vec![],
+        }
+    }
+
+    /// Number of memory words the instruction occupies.
+    pub(crate) fn len(&self) -> usize {
+        1 + self.params().len()
+    }
+
+    /// The canonical opcode word for the instruction, including parameter modes.
+    pub(crate) fn opcode(&self) -> Mem {
+        let base = match self {
+            Op::Add(..) => 1,
+            Op::Mul(..) => 2,
+            Op::In(_) => 3,
+            Op::Out(_) => 4,
+            Op::JumpIfTrue(..) => 5,
+            Op::JumpIfFalse(..) => 6,
+            Op::LessThan(..) => 7,
+            Op::Equals(..) => 8,
+            Op::AdjustRelBase(_) => 9,
+            Op::End => 99,
+        };
+        let mut scale = 100;
+        let mut opcode = base;
+        for p in self.params() {
+            opcode += p.mode() * scale;
+            scale *= 10;
+        }
+        opcode
+    }
+}
+
+/// How `ADD`, `MUL` and relative addressing treat results that don't fit in
+/// a memory cell. For cells of (almost) any size, see `bignum::BigMachine`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum Arithmetic {
+    /// Results wrap around, two's complement style.
+    #[default]
+    Wrapping,
+    /// Overflow is an error.
+    Checked,
+}
+
+impl Arithmetic {
+    pub(crate) fn add(self, lhs: Mem, rhs: Mem) -> Result<Mem, ErrorKind> {
+        match self {
+            Arithmetic::Wrapping => Ok(lhs.wrapping_add(rhs)),
+            Arithmetic::Checked => lhs.checked_add(rhs).ok_or(ErrorKind::Overflow),
+        }
+    }
+
+    pub(crate) fn mul(self, lhs: Mem, rhs: Mem) -> Result<Mem, ErrorKind> {
+        match self {
+            Arithmetic::Wrapping => Ok(lhs.wrapping_mul(rhs)),
+            Arithmetic::Checked => lhs.checked_mul(rhs).ok_or(ErrorKind::Overflow),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum ErrorKind {
+    InvalidOpcode,
+    InvalidParameterMode(Mem),
+    WriteToImmediate,
+    InputExhausted,
+    InputFailed(String),
+    NegativeAddress(Mem),
+    Overflow,
+    MemoryLimit,
+}
+
+/// An error raised while executing an instruction, together with the machine
+/// state at the faulting instruction.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct IntcodeError {
+    pub kind: ErrorKind,
+    pub ip: Ptr,
+    pub opcode: Mem,
+    pub rel_base: Mem,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErrorKind::InvalidOpcode => write!(f, "invalid opcode"),
+            ErrorKind::InvalidParameterMode(mode) => write!(f, "invalid parameter mode {}", mode),
+            ErrorKind::WriteToImmediate => write!(f, "writing to immediate"),
+            ErrorKind::InputExhausted => write!(f, "input exhausted"),
+            ErrorKind::InputFailed(msg) => write!(f, "input failed: {}", msg),
+            ErrorKind::NegativeAddress(addr) => write!(f, "negative address {}", addr),
+            ErrorKind::Overflow => write!(f, "arithmetic overflow"),
+            ErrorKind::MemoryLimit => write!(f, "too many cells written beyond address {}", DENSE_LIMIT),
+        }
+    }
+}
+
+impl std::fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at ip {} (opcode {}, rel_base {})", self.kind, self.ip, self.opcode, self.rel_base)
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
 pub trait InputOutput {
-    fn next_input(&mut self) -> Result<Mem, String>;
+    fn next_input(&mut self) -> Result<Mem, ErrorKind>;
     fn next_output(&mut self, x: Mem);
 }
 
 pub trait Input {
-    fn next_input(&mut self) -> Result<Mem, String>;
+    fn next_input(&mut self) -> Result<Mem, ErrorKind>;
 }
 
 pub trait Output {
@@ -78,18 +294,18 @@ struct InputOutputWrapper<'a> {
 }
 
 impl<'a> InputOutput for InputOutputWrapper<'a> {
-    fn next_input(&mut self) -> Result<i64, String> {
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
         self.input.next_input()
     }
 
-    fn next_output(&mut self, x: i64) {
+    fn next_output(&mut self, x: Mem) {
         self.output.next_output(x)
     }
 }
 
 impl Input for Vec<Mem> {
-    fn next_input(&mut self) -> Result<Mem, String> {
-        self.pop().ok_or(String::from("not enough inputs in vector"))
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+        self.pop().ok_or(ErrorKind::InputExhausted)
     }
 }
 
@@ -107,29 +323,29 @@ fn get_flag(opcode: Mem, index: u32) -> i32 {
     (flags % 10) as i32
 }
 
-fn decode_param(m: &Memory, ptr: Ptr, opcode: Mem, index: u32) -> Result<Param, String> {
+pub(crate) fn decode_param(m: &Memory, ptr: Ptr, opcode: Mem, index: u32) -> Result<Param, ErrorKind> {
     let val = m.read(ptr);
 
     let flag = get_flag(opcode, index);
     if flag == 0 {
-        Ok(Param::Pos(val as Ptr))
+        Ok(Param::Pos(to_ptr(val)?))
     } else if flag == 1 {
         Ok(Param::Imm(val))
     } else if flag == 2 {
         Ok(Param::Rel(val))
     } else {
-        Err(String::from("invalid parameter mode"))
+        Err(ErrorKind::InvalidParameterMode(flag as Mem))
     }
 }
 
-fn decode_3_params(m: &Memory, ptr: Ptr, opcode: Mem) -> Result<(Param,Param,Param), String> {
+fn decode_3_params(m: &Memory, ptr: Ptr, opcode: Mem) -> Result<(Param,Param,Param), ErrorKind> {
     let p0 = decode_param(m, ptr, opcode, 0)?;
     let p1 = decode_param(m, ptr+1, opcode, 1)?;
     let p2 = decode_param(m, ptr+2, opcode, 2)?;
     Ok((p0,p1,p2))
 }
 
-fn decode_instr(m: &Memory, ip: Ptr) -> Result<Op, String> {
+pub fn decode_instr(m: &Memory, ip: Ptr) -> Result<Op, ErrorKind> {
     let opcode = m.read(ip);
     match opcode % 100 {
         1 => {
@@ -173,12 +389,35 @@ fn decode_instr(m: &Memory, ip: Ptr) -> Result<Op, String> {
         99 => {
             Ok(Op::End)
         },
-        _ => Err(String::from("invalid opcode")),
+        _ => Err(ErrorKind::InvalidOpcode),
     }
 }
 
 ////////////////////////////////////////////////////////////////
 
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub struct MemWrite {
+    pub addr: Ptr,
+    pub old: Mem,
+    pub new: Mem,
+}
+
+/// Everything a single executed instruction did. `operands` holds the values
+/// of the parameters that were read, in order; for `HLT`, `new_ip` is `ip`.
+pub struct TraceStep<'a> {
+    pub ip: Ptr,
+    pub rel_base: Mem,
+    pub op: &'a Op,
+    pub operands: &'a [Mem],
+    pub write: Option<MemWrite>,
+    pub new_ip: Ptr,
+    pub new_rel_base: Mem,
+}
+
+pub trait Tracer {
+    fn trace(&mut self, step: &TraceStep);
+}
+
 pub enum StepResult {
     Continue(Ptr, Mem), // ip, rel_base
     End,
@@ -189,7 +428,7 @@ pub fn step_program_splitio(
     ip: Ptr,
     rel_base: Mem,
     input: &mut dyn Input,
-    output: &mut dyn Output) -> Result<StepResult, String>
+    output: &mut dyn Output) -> Result<StepResult, IntcodeError>
 {
     step_program(mem, ip, rel_base, &mut InputOutputWrapper{input, output})
 }
@@ -198,97 +437,466 @@ pub fn step_program(
     mem: &mut Memory,
     ip: Ptr,
     rel_base: Mem,
-    io: &mut dyn InputOutput) -> Result<StepResult, String>
+    io: &mut dyn InputOutput) -> Result<StepResult, IntcodeError>
+{
+    step_program_with(mem, ip, rel_base, io, Arithmetic::default())
+}
+
+pub fn step_program_with(
+    mem: &mut Memory,
+    ip: Ptr,
+    rel_base: Mem,
+    io: &mut dyn InputOutput,
+    arith: Arithmetic) -> Result<StepResult, IntcodeError>
+{
+    let mut effects = Effects::default();
+    execute(mem, ip, rel_base, io, arith, &mut effects)
+        .map(|(_, result)| result)
+        .map_err(|kind| IntcodeError { kind, ip, opcode: mem.read(ip), rel_base })
+}
+
+pub fn step_program_traced(
+    mem: &mut Memory,
+    ip: Ptr,
+    rel_base: Mem,
+    io: &mut dyn InputOutput,
+    tracer: &mut dyn Tracer) -> Result<StepResult, IntcodeError>
+{
+    step_program_traced_with(mem, ip, rel_base, io, tracer, Arithmetic::default())
+}
+
+pub fn step_program_traced_with(
+    mem: &mut Memory,
+    ip: Ptr,
+    rel_base: Mem,
+    io: &mut dyn InputOutput,
+    tracer: &mut dyn Tracer,
+    arith: Arithmetic) -> Result<StepResult, IntcodeError>
+{
+    let mut effects = Effects::default();
+    let (op, result) = execute(mem, ip, rel_base, io, arith, &mut effects)
+        .map_err(|kind| IntcodeError { kind, ip, opcode: mem.read(ip), rel_base })?;
+    let (new_ip, new_rel_base) = match result {
+        StepResult::Continue(new_ip, new_rel_base) => (new_ip, new_rel_base),
+        StepResult::End => (ip, rel_base),
+    };
+    tracer.trace(&TraceStep {
+        ip,
+        rel_base,
+        op: &op,
+        operands: &effects.operands[..effects.count],
+        write: effects.write,
+        new_ip,
+        new_rel_base,
+    });
+    Ok(result)
+}
+
+/// The values read and the memory cell written by a single instruction.
+#[derive(Default)]
+struct Effects {
+    operands: [Mem; 2],
+    count: usize,
+    write: Option<MemWrite>,
+}
+
+impl Effects {
+    fn read(&mut self, mem: &Memory, param: &Param, rel_base: Mem, arith: Arithmetic) -> Result<Mem, ErrorKind> {
+        let val = mem.read_param(param, rel_base, arith)?;
+        self.operands[self.count] = val;
+        self.count += 1;
+        Ok(val)
+    }
+
+    fn write(&mut self, mem: &mut Memory, param: &Param, val: Mem, rel_base: Mem, arith: Arithmetic) -> Result<(), ErrorKind> {
+        self.write = Some(mem.write_param(param, val, rel_base, arith)?);
+        Ok(())
+    }
+}
+
+fn execute(
+    mem: &mut Memory,
+    ip: Ptr,
+    rel_base: Mem,
+    io: &mut dyn InputOutput,
+    arith: Arithmetic,
+    fx: &mut Effects) -> Result<(Op, StepResult), ErrorKind>
 {
-    let op = decode_instr(&mem, ip)?;
-    let (new_ip, new_rel_base) = match op {
+    let op = decode_instr(mem, ip)?;
+    let (new_ip, new_rel_base) = match &op {
         Op::Add(lhs, rhs, dest) => {
-            mem.write_param(
-                &dest,
-                mem.read_param(&lhs, rel_base) + mem.read_param(&rhs, rel_base),
-                rel_base)?;
+            let val = arith.add(fx.read(mem, lhs, rel_base, arith)?, fx.read(mem, rhs, rel_base, arith)?)?;
+            fx.write(mem, dest, val, rel_base, arith)?;
             (ip+4, rel_base)
         },
         Op::Mul(lhs, rhs, dest) => {
-            mem.write_param(
-                &dest,
-                mem.read_param(&lhs, rel_base) * mem.read_param(&rhs, rel_base),
-                rel_base)?;
+            let val = arith.mul(fx.read(mem, lhs, rel_base, arith)?, fx.read(mem, rhs, rel_base, arith)?)?;
+            fx.write(mem, dest, val, rel_base, arith)?;
             (ip+4, rel_base)
         },
         Op::In(p) => {
-            mem.write_param(&p, io.next_input()?, rel_base)?;
+            fx.write(mem, p, io.next_input()?, rel_base, arith)?;
             (ip+2, rel_base)
         },
         Op::Out(p) => {
-            io.next_output(mem.read_param(&p, rel_base));
+            io.next_output(fx.read(mem, p, rel_base, arith)?);
             (ip+2, rel_base)
         },
         Op::JumpIfTrue(expr, dest) => {
-            if mem.read_param(&expr, rel_base) != 0 {
-                (mem.read_param(&dest, rel_base) as Ptr, rel_base)
+            if fx.read(mem, expr, rel_base, arith)? != 0 {
+                (to_ptr(fx.read(mem, dest, rel_base, arith)?)?, rel_base)
             } else {
                 (ip+3, rel_base)
             }
         },
         Op::JumpIfFalse(expr, dest) => {
-            if mem.read_param(&expr, rel_base) == 0 {
-                (mem.read_param(&dest, rel_base) as Ptr, rel_base)
+            if fx.read(mem, expr, rel_base, arith)? == 0 {
+                (to_ptr(fx.read(mem, dest, rel_base, arith)?)?, rel_base)
             } else {
                 (ip+3, rel_base)
             }
         },
         Op::LessThan(lhs, rhs, dest) => {
-            mem.write_param(
-                &dest,
-                (mem.read_param(&lhs, rel_base) < mem.read_param(&rhs, rel_base)) as Mem,
-                rel_base)?;
+            let val = (fx.read(mem, lhs, rel_base, arith)? < fx.read(mem, rhs, rel_base, arith)?) as Mem;
+            fx.write(mem, dest, val, rel_base, arith)?;
             (ip+4, rel_base)
         },
         Op::Equals(lhs, rhs, dest) => {
-            mem.write_param(
-                &dest,
-                (mem.read_param(&lhs, rel_base) == mem.read_param(&rhs, rel_base)) as Mem,
-                rel_base)?;
+            let val = (fx.read(mem, lhs, rel_base, arith)? == fx.read(mem, rhs, rel_base, arith)?) as Mem;
+            fx.write(mem, dest, val, rel_base, arith)?;
             (ip+4, rel_base)
         },
         Op::AdjustRelBase(adjustment) => {
-            (ip+2, rel_base+mem.read_param(&adjustment, rel_base))
+            (ip+2, arith.add(rel_base, fx.read(mem, adjustment, rel_base, arith)?)?)
         },
-        Op::End => return Ok(StepResult::End)
+        Op::End => return Ok((op, StepResult::End))
     };
-    return Ok(StepResult::Continue(new_ip, new_rel_base))
+    Ok((op, StepResult::Continue(new_ip, new_rel_base)))
 }
 
 pub fn run_program_splitio(
     memdata: Vec<Mem>,
     input: &mut dyn Input,
-    output: &mut dyn Output) -> Result<Vec<Mem>, String>
+    output: &mut dyn Output) -> Result<Vec<Mem>, IntcodeError>
 {
     run_program(memdata, &mut InputOutputWrapper{input, output})
 }
 
 pub fn run_program(
     memdata: Vec<Mem>,
-    io: &mut dyn InputOutput) -> Result<Vec<Mem>, String>
+    io: &mut dyn InputOutput) -> Result<Vec<Mem>, IntcodeError>
+{
+    run_program_with(memdata, io, Arithmetic::default())
+}
+
+pub fn run_program_with(
+    memdata: Vec<Mem>,
+    io: &mut dyn InputOutput,
+    arith: Arithmetic) -> Result<Vec<Mem>, IntcodeError>
 {
-    let mut mem = Memory { memory: memdata };
+    let mut mem = Memory::new(memdata);
     let mut ip: Ptr = 0;
     let mut rel_base: Mem = 0;
     loop {
-        let (new_ip,new_rel_base) = match step_program(&mut mem, ip, rel_base, io)? {
+        let (new_ip,new_rel_base) = match step_program_with(&mut mem, ip, rel_base, io, arith)? {
             StepResult::Continue(new_ip, new_rel_base) => (new_ip, new_rel_base),
-            StepResult::End => return Ok(mem.memory)
+            StepResult::End => return Ok(mem.into_vec())
         };
         ip = new_ip;
         rel_base = new_rel_base;
     }
 }
 
-pub fn needs_input(mem: &Memory, ip: Ptr) -> Result<bool,String> {
-    match decode_instr(mem, ip)? {
-        Op::In(_) => Ok(true),
-        _ => Ok(false)
+pub fn run_program_traced(
+    memdata: Vec<Mem>,
+    io: &mut dyn InputOutput,
+    tracer: &mut dyn Tracer) -> Result<Vec<Mem>, IntcodeError>
+{
+    let mut mem = Memory::new(memdata);
+    let mut ip: Ptr = 0;
+    let mut rel_base: Mem = 0;
+    loop {
+        let (new_ip,new_rel_base) = match step_program_traced(&mut mem, ip, rel_base, io, tracer)? {
+            StepResult::Continue(new_ip, new_rel_base) => (new_ip, new_rel_base),
+            StepResult::End => return Ok(mem.into_vec())
+        };
+        ip = new_ip;
+        rel_base = new_rel_base;
+    }
+}
+
+/// Limits on how long a bounded run may go on. The deadline is checked every
+/// few thousand instructions, and can't interrupt a blocking input call.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct Budget {
+    pub max_steps: Option<u64>,
+    pub deadline: Option<Instant>,
+}
+
+const DEADLINE_CHECK_INTERVAL: u64 = 4096;
+
+impl Budget {
+    pub fn steps(max_steps: u64) -> Self {
+        Budget { max_steps: Some(max_steps), deadline: None }
+    }
+
+    pub fn timeout(duration: Duration) -> Self {
+        Budget { max_steps: None, deadline: Some(Instant::now() + duration) }
+    }
+
+    pub fn with_steps(self, max_steps: u64) -> Self {
+        Budget { max_steps: Some(max_steps), ..self }
+    }
+
+    pub fn with_timeout(self, duration: Duration) -> Self {
+        Budget { deadline: Some(Instant::now() + duration), ..self }
+    }
+
+    fn exhausted(&self, steps: u64) -> bool {
+        if self.max_steps.is_some_and(|max| steps >= max) {
+            return true;
+        }
+        match self.deadline {
+            Some(deadline) => steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline,
+            None => false,
+        }
+    }
+}
+
+pub enum RunOutcome {
+    Halted(Vec<Mem>),
+    /// The budget ran out; the machine can be handed to `continue_program_bounded`.
+    BudgetExhausted(Machine),
+}
+
+pub fn run_program_bounded(
+    memdata: Vec<Mem>,
+    io: &mut dyn InputOutput,
+    budget: Budget) -> Result<RunOutcome, IntcodeError>
+{
+    continue_program_bounded(Machine::new(memdata), io, budget)
+}
+
+/// Continues a machine with `io` supplying all input; the machine's own input
+/// queue is not used.
+pub fn continue_program_bounded(
+    mut machine: Machine,
+    io: &mut dyn InputOutput,
+    budget: Budget) -> Result<RunOutcome, IntcodeError>
+{
+    let mut steps = 0;
+    while !machine.halted {
+        if budget.exhausted(steps) {
+            return Ok(RunOutcome::BudgetExhausted(machine));
+        }
+        match step_program_with(&mut machine.memory, machine.ip, machine.rel_base, io, machine.arithmetic)? {
+            StepResult::Continue(ip, rel_base) => {
+                machine.ip = ip;
+                machine.rel_base = rel_base;
+            },
+            StepResult::End => machine.halted = true,
+        }
+        steps += 1;
+    }
+    Ok(RunOutcome::Halted(machine.into_memory()))
+}
+
+pub fn needs_input(mem: &Memory, ip: Ptr) -> bool {
+    matches!(decode_instr(mem, ip), Ok(Op::In(_)))
+}
+
+////////////////////////////////////////////////////////////////
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Event {
+    NeedInput,
+    Output(Mem),
+    Halted,
+}
+
+/// An intcode program together with its execution state. Inputs are queued
+/// with `push_input`, and `resume` runs until the program produces an event.
+#[derive(Clone)]
+pub struct Machine {
+    memory: Memory,
+    ip: Ptr,
+    rel_base: Mem,
+    input: VecDeque<Mem>,
+    halted: bool,
+    arithmetic: Arithmetic,
+}
+
+struct MachineIO<'a> {
+    input: &'a mut VecDeque<Mem>,
+    output: Option<Mem>,
+}
+
+impl<'a> InputOutput for MachineIO<'a> {
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+        self.input.pop_front().ok_or(ErrorKind::InputExhausted)
+    }
+
+    fn next_output(&mut self, x: Mem) {
+        self.output = Some(x)
+    }
+}
+
+impl Machine {
+    pub fn new(program: Vec<Mem>) -> Self {
+        Machine {
+            memory: Memory::new(program),
+            ip: 0,
+            rel_base: 0,
+            input: VecDeque::new(),
+            halted: false,
+            arithmetic: Arithmetic::default(),
+        }
+    }
+
+    pub(crate) fn from_parts(memory: Memory, ip: Ptr, rel_base: Mem, input: VecDeque<Mem>, halted: bool) -> Self {
+        Machine { memory, ip, rel_base, input, halted, arithmetic: Arithmetic::default() }
+    }
+
+    pub fn with_arithmetic(self, arithmetic: Arithmetic) -> Self {
+        Machine { arithmetic, ..self }
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn ip(&self) -> Ptr {
+        self.ip
+    }
+
+    pub fn rel_base(&self) -> Mem {
+        self.rel_base
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn into_memory(self) -> Vec<Mem> {
+        self.memory.into_vec()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn push_input(&mut self, x: Mem) {
+        self.input.push_back(x)
+    }
+
+    pub fn pending_input(&self) -> usize {
+        self.input.len()
+    }
+
+    pub(crate) fn input_queue(&self) -> &VecDeque<Mem> {
+        &self.input
+    }
+
+    pub fn resume(&mut self) -> Result<Event, IntcodeError> {
+        self.run(None)
+    }
+
+    pub fn resume_traced(&mut self, tracer: &mut dyn Tracer) -> Result<Event, IntcodeError> {
+        self.run(Some(tracer))
+    }
+
+    /// Executes a single instruction. Returns `None` if the instruction
+    /// produced no event; on `NeedInput` nothing was executed.
+    pub fn step(&mut self) -> Result<Option<Event>, IntcodeError> {
+        self.step_with(None)
+    }
+
+    pub fn step_traced(&mut self, tracer: &mut dyn Tracer) -> Result<Option<Event>, IntcodeError> {
+        self.step_with(Some(tracer))
+    }
+
+    fn step_with(&mut self, tracer: Option<&mut dyn Tracer>) -> Result<Option<Event>, IntcodeError> {
+        if self.halted {
+            return Ok(Some(Event::Halted));
+        }
+        if self.input.is_empty() && needs_input(&self.memory, self.ip) {
+            return Ok(Some(Event::NeedInput));
+        }
+        let mut io = MachineIO { input: &mut self.input, output: None };
+        let result = match tracer {
+            Some(tracer) => step_program_traced_with(&mut self.memory, self.ip, self.rel_base, &mut io, tracer, self.arithmetic)?,
+            None => step_program_with(&mut self.memory, self.ip, self.rel_base, &mut io, self.arithmetic)?,
+        };
+        match result {
+            StepResult::Continue(ip, rel_base) => {
+                self.ip = ip;
+                self.rel_base = rel_base;
+            },
+            StepResult::End => {
+                self.halted = true;
+                return Ok(Some(Event::Halted));
+            },
+        }
+        Ok(io.output.map(Event::Output))
+    }
+
+    /// Like `resume`, but gives up when the budget runs out, in which case
+    /// `None` is returned and the machine can be resumed later.
+    pub fn resume_bounded(&mut self, budget: Budget) -> Result<Option<Event>, IntcodeError> {
+        let mut steps = 0;
+        loop {
+            if budget.exhausted(steps) {
+                return Ok(None);
+            }
+            if let Some(event) = self.step_with(None)? {
+                return Ok(Some(event));
+            }
+            steps += 1;
+        }
+    }
+
+    fn run(&mut self, mut tracer: Option<&mut dyn Tracer>) -> Result<Event, IntcodeError> {
+        loop {
+            let event = match tracer {
+                Some(ref mut tracer) => self.step_with(Some(&mut **tracer))?,
+                None => self.step_with(None)?,
+            };
+            if let Some(event) = event {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// A copy of the machine that shares all memory pages with this one
+    /// until either of them writes to a page.
+    pub fn fork(&self) -> Machine {
+        self.clone()
+    }
+
+    /// Overwrites a memory cell, e.g. to patch a program before running it.
+    pub fn poke(&mut self, addr: Ptr, val: Mem) {
+        self.memory.write(addr, val)
+    }
+
+    /// Like `poke`, but subject to the memory limit like the program's own
+    /// writes.
+    pub(crate) fn store(&mut self, addr: Ptr, val: Mem) -> Result<(), ErrorKind> {
+        self.memory.store(addr, val)
+    }
+
+    pub(crate) fn set_ip(&mut self, ip: Ptr) {
+        self.ip = ip;
+    }
+
+    /// Moves the machine back to before an instruction, putting back the
+    /// input it consumed.
+    pub(crate) fn rewind(&mut self, ip: Ptr, rel_base: Mem, consumed: Option<Mem>) {
+        self.ip = ip;
+        self.rel_base = rel_base;
+        self.halted = false;
+        if let Some(x) = consumed {
+            self.input.push_front(x);
+        }
     }
 }
 
@@ -298,7 +906,7 @@ mod tests {
 
     #[test]
     fn test_decode() {
-        assert_eq!(decode_instr(&Memory { memory: vec![1002, 4, 3, 4, 33] }, 0),
+        assert_eq!(decode_instr(&Memory::new(vec![1002, 4, 3, 4, 33]), 0),
                    Ok(Op::Mul(Param::Pos(4), Param::Imm(3), Param::Pos(4))));
     }
 
@@ -338,11 +946,147 @@ mod tests {
         assert_eq!(*out.first().unwrap(), 1125899906842624);
     }
 
+    #[test]
+    fn test_arithmetic() {
+        // MUL #34915192 #34915192 [7]; MUL [7] [7] [7]; OUT [7]; HLT
+        let mem = vec![1102,34915192,34915192,11,2,11,11,11,4,11,99,0];
+        let mut machine = Machine::new(mem.clone());
+        machine.step().unwrap();
+        assert_eq!(machine.resume(), Ok(Event::Output(1219070632396864i64.wrapping_mul(1219070632396864))));
+
+        let mut checked = Machine::new(mem.clone()).with_arithmetic(Arithmetic::Checked);
+        assert_eq!(checked.resume(), Err(IntcodeError { kind: ErrorKind::Overflow, ip: 4, opcode: 2, rel_base: 0 }));
+        let io = &mut InputOutputWrapper { input: &mut vec![], output: &mut vec![] };
+        let err = run_program_with(mem, io, Arithmetic::Checked).unwrap_err();
+        assert_eq!((err.kind, err.ip), (ErrorKind::Overflow, 4));
+    }
+
+
+    #[test]
+    fn test_machine_events() {
+        // IN [9]; OUT [9]; OUT #7; HLT
+        let mut machine = Machine::new(vec![3, 9, 4, 9, 104, 7, 99, 0, 0, 0]);
+        assert_eq!(machine.resume(), Ok(Event::NeedInput));
+        assert_eq!(machine.resume(), Ok(Event::NeedInput));
+        machine.push_input(42);
+        assert_eq!(machine.resume(), Ok(Event::Output(42)));
+        assert_eq!(machine.resume(), Ok(Event::Output(7)));
+        assert_eq!(machine.resume(), Ok(Event::Halted));
+        assert_eq!(machine.resume(), Ok(Event::Halted));
+        assert_eq!(machine.into_memory()[9], 42);
+    }
+
+    #[test]
+    fn test_machine_step() {
+        // ADD #1 #2 [9]; OUT [9]; HLT
+        let mut machine = Machine::new(vec![1101, 1, 2, 9, 4, 9, 99, 0, 0, 0]);
+        assert_eq!(machine.step(), Ok(None));
+        assert_eq!(machine.ip(), 4);
+        machine.poke(9, 40);
+        assert_eq!(machine.step(), Ok(Some(Event::Output(40))));
+        assert_eq!(machine.step(), Ok(Some(Event::Halted)));
+        assert!(machine.is_halted());
+    }
+
+    #[test]
+    fn test_fork() {
+        let mut program = vec![0; 3 * PAGE_SIZE];
+        // IN [600]; OUT [600]; HLT
+        program[..5].copy_from_slice(&[3, 600, 4, 600, 99]);
+        let parent = Machine::new(program);
+        let mut child = parent.fork();
+        child.push_input(5);
+        assert_eq!(child.resume(), Ok(Event::Output(5)));
+
+        assert_eq!(parent.memory().read(600), 0);
+        assert_eq!(child.memory().read(600), 5);
+        assert!(Arc::ptr_eq(&parent.memory().pages[0], &child.memory().pages[0]));
+        assert!(Arc::ptr_eq(&parent.memory().pages[1], &child.memory().pages[1]));
+        assert!(!Arc::ptr_eq(&parent.memory().pages[2], &child.memory().pages[2]));
+    }
+
+    #[test]
+    fn test_budget() {
+        // loop: ADD [7] #1 [7]; JT #1 #loop
+        let program = vec![1001, 7, 1, 7, 1105, 1, 0, 0];
+        let (mut input, mut output) = (vec![], vec![]);
+        let mut io = InputOutputWrapper { input: &mut input, output: &mut output };
+        let machine = match run_program_bounded(program, &mut io, Budget::steps(10)).unwrap() {
+            RunOutcome::BudgetExhausted(machine) => machine,
+            RunOutcome::Halted(_) => panic!("infinite loop halted"),
+        };
+        assert_eq!(machine.memory().read(7), 5);
+        let machine = match continue_program_bounded(machine, &mut io, Budget::steps(4)).unwrap() {
+            RunOutcome::BudgetExhausted(machine) => machine,
+            RunOutcome::Halted(_) => panic!("infinite loop halted"),
+        };
+        assert_eq!(machine.memory().read(7), 7);
+
+        let mut machine = Machine::new(vec![1001, 7, 1, 7, 1105, 1, 0, 0]);
+        assert_eq!(machine.resume_bounded(Budget::timeout(Duration::from_millis(10))), Ok(None));
+        assert_eq!(machine.resume_bounded(Budget::steps(3)), Ok(None));
+
+        // OUT #3; HLT
+        match run_program_bounded(vec![104, 3, 99], &mut io, Budget::steps(2)).unwrap() {
+            RunOutcome::Halted(mem) => assert_eq!(mem, vec![104, 3, 99]),
+            RunOutcome::BudgetExhausted(_) => panic!("budget should suffice"),
+        }
+        let mut machine = Machine::new(vec![104, 3, 99]);
+        assert_eq!(machine.resume_bounded(Budget::steps(1)), Ok(Some(Event::Output(3))));
+    }
+
+    #[test]
+    fn test_errors() {
+        // ARB #5; ADD #1 #1 #0
+        let err = run_program_splitio(vec![109, 5, 11101, 1, 1, 0], &mut vec![], &mut vec![]).unwrap_err();
+        assert_eq!(err, IntcodeError { kind: ErrorKind::WriteToImmediate, ip: 2, opcode: 11101, rel_base: 5 });
+
+        let err = run_program_splitio(vec![3, 0, 42], &mut vec![], &mut vec![]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InputExhausted);
+
+        let err = run_program_splitio(vec![3, 0, 42], &mut vec![1], &mut vec![]).unwrap_err();
+        assert_eq!(err, IntcodeError { kind: ErrorKind::InvalidOpcode, ip: 2, opcode: 42, rel_base: 0 });
+
+        let err = run_program_splitio(vec![304, 0], &mut vec![], &mut vec![]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidParameterMode(3));
+
+        // ARB #-3; OUT rel+0
+        let err = run_program_splitio(vec![109, -3, 204, 0], &mut vec![], &mut vec![]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NegativeAddress(-3));
+    }
 
     #[test]
     fn test_memory() {
-        let mem = Memory { memory: vec![1, 2, 3, 4] };
+        let mem = Memory::new(vec![1, 2, 3, 4]);
         assert_eq!(mem.read(2), 3);
         assert_eq!(mem.read(119), 0);
     }
+
+    #[test]
+    fn test_far_memory() {
+        let mut mem = Memory::new(vec![1, 2, 3, 4]);
+        mem.write(1_000_000_000_000, 17);
+        mem.write(100, 5);
+        assert_eq!(mem.read(1_000_000_000_000), 17);
+        assert_eq!(mem.read(1_000_000_000_001), 0);
+        assert_eq!(mem.read(100), 5);
+        assert_eq!(mem.len(), 101);
+
+        // ADD #1 #2 [10^15]; OUT [10^15]; HLT
+        let mut out = vec![];
+        let res = run_program_splitio(
+            vec![1101, 1, 2, 1_000_000_000_000_000, 4, 1_000_000_000_000_000, 99],
+            &mut vec![], &mut out).unwrap();
+        assert_eq!(out, vec![3]);
+        assert_eq!(res.len(), 7);
+
+        // The sparse map is bounded, but its cells can still be rewritten.
+        let mut mem = Memory::new(vec![]);
+        for ptr in DENSE_LIMIT..DENSE_LIMIT + FAR_LIMIT {
+            mem.store(ptr, 1).unwrap();
+        }
+        assert_eq!(mem.store(DENSE_LIMIT + FAR_LIMIT, 1), Err(ErrorKind::MemoryLimit));
+        assert_eq!(mem.store(DENSE_LIMIT, 2), Ok(()));
+        assert_eq!(mem.store(5, 2), Ok(()));
+    }
 }
@@ -0,0 +1,894 @@
+use std::collections::{HashMap, HashSet};
+use crate::asm::assemble;
+use crate::intcode::Mem;
+
+// A small structured language that compiles to intcode:
+//
+//     var data[100];                  // global array, zero-filled
+//     var count = 0;                  // global, with a constant initial value
+//
+//     fn sum(n) {
+//         var i = 0;
+//         var total = 0;
+//         while (i < n) {
+//             total = total + data[i];
+//             i = i + 1;
+//         }
+//         return total;
+//     }
+//
+//     fn main() {
+//         count = input();
+//         ...
+//         output(sum(count));
+//     }
+//
+// Operators, loosest first: `==` `!=` `<` `<=` `>` `>=`, then `+` `-`, then
+// `*`, then unary `-` and `!`. There is no division. Conditions are true when
+// non-zero, and comparisons give 0 or 1. Functions always return a value, 0
+// if they end without `return`. Locals are visible in the whole function;
+// arrays can only be global, and indices aren't checked.
+//
+// Execution starts at `main`. Every call gets a frame on a stack above the
+// program image, addressed through the relative base: rel+0 holds the return
+// address, followed by the parameters, the locals and temporaries. Results
+// are passed back in a fixed cell. Array elements are accessed by writing
+// the element's address into the operand of the instruction that follows.
+
+#[derive(Debug,PartialEq,Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+////////////////////////////////////////////////////////////////
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Token {
+    Num(Mem),
+    Ident(String),
+    Punct(&'static str),
+}
+
+// Longer operators first, so that `<=` isn't read as `<`.
+const PUNCTUATION: [&str; 19] = [
+    "==", "!=", "<=", ">=",
+    "(", ")", "{", "}", "[", "]", ";", ",", "=", "<", ">", "+", "-", "*", "!",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let mut tokens = Vec::new();
+    for (ix, raw_line) in source.lines().enumerate() {
+        let line = ix + 1;
+        let mut rest = match raw_line.find("//") {
+            Some(pos) => &raw_line[..pos],
+            None => raw_line,
+        }.trim_start();
+        while !rest.is_empty() {
+            let c = rest.chars().next().unwrap();
+            let len = if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let val = rest[..len].parse()
+                    .map_err(|_| CompileError { line, message: format!("number '{}' too large", &rest[..len]) })?;
+                tokens.push((Token::Num(val), line));
+                len
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                tokens.push((Token::Ident(rest[..len].to_string()), line));
+                len
+            } else if let Some(p) = PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                tokens.push((Token::Punct(p), line));
+                p.len()
+            } else {
+                return Err(CompileError { line, message: format!("unexpected character '{}'", c) });
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+////////////////////////////////////////////////////////////////
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+enum Expr {
+    Num(Mem),
+    Var(String),
+    Index(String, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+enum StmtKind {
+    Var(String, Option<Expr>),
+    Assign(String, Expr),
+    Store(String, Expr, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+struct Stmt {
+    line: usize,
+    kind: StmtKind,
+}
+
+struct Function {
+    line: usize,
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+enum Global {
+    Scalar(Mem),
+    Array(usize),
+}
+
+#[derive(Default)]
+struct Program {
+    globals: Vec<(usize, String, Global)>,
+    functions: Vec<Function>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some((_, line)) => *line,
+            None => self.tokens.last().map_or(1, |(_, line)| *line),
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError { line: self.line(), message })
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Some(Token::Num(n)) => format!("'{}'", n),
+            Some(Token::Ident(s)) => format!("'{}'", s),
+            Some(Token::Punct(p)) => format!("'{}'", p),
+            None => String::from("end of input"),
+        }
+    }
+
+    fn accept(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if self.accept(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}', found {}", punct, self.describe()))
+        }
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(s)) if s == keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Ident(s)) if !KEYWORDS.contains(&s.as_str()) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            },
+            _ => self.error(format!("expected a name, found {}", self.describe())),
+        }
+    }
+
+    fn number(&mut self) -> Result<Mem, CompileError> {
+        let negative = self.accept("-");
+        match self.peek() {
+            Some(&Token::Num(n)) => {
+                self.pos += 1;
+                Ok(if negative { -n } else { n })
+            },
+            _ => self.error(format!("expected a number, found {}", self.describe())),
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, CompileError> {
+        let mut program = Program::default();
+        while self.peek().is_some() {
+            let line = self.line();
+            if self.accept_keyword("var") {
+                let name = self.ident()?;
+                let global = if self.accept("[") {
+                    let size = self.number()?;
+                    self.expect("]")?;
+                    if size <= 0 {
+                        return Err(CompileError { line, message: format!("array '{}' needs a positive size", name) });
+                    }
+                    Global::Array(size as usize)
+                } else if self.accept("=") {
+                    Global::Scalar(self.number()?)
+                } else {
+                    Global::Scalar(0)
+                };
+                self.expect(";")?;
+                program.globals.push((line, name, global));
+            } else if self.accept_keyword("fn") {
+                let name = self.ident()?;
+                self.expect("(")?;
+                let mut params = Vec::new();
+                if !self.accept(")") {
+                    loop {
+                        params.push(self.ident()?);
+                        if self.accept(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                let body = self.block()?;
+                program.functions.push(Function { line, name, params, body });
+            } else {
+                return self.error(format!("expected 'var' or 'fn', found {}", self.describe()));
+            }
+        }
+        Ok(program)
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.accept("}") {
+            if self.peek().is_none() {
+                return self.error(String::from("unterminated block"));
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let kind = if self.accept_keyword("var") {
+            let name = self.ident()?;
+            if matches!(self.peek(), Some(Token::Punct("["))) {
+                return self.error(String::from("arrays must be global"));
+            }
+            let init = if self.accept("=") { Some(self.expr()?) } else { None };
+            self.expect(";")?;
+            StmtKind::Var(name, init)
+        } else if self.accept_keyword("if") {
+            self.if_rest()?
+        } else if self.accept_keyword("while") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            StmtKind::While(cond, self.block()?)
+        } else if self.accept_keyword("return") {
+            let value = if self.accept(";") {
+                None
+            } else {
+                let value = self.expr()?;
+                self.expect(";")?;
+                Some(value)
+            };
+            StmtKind::Return(value)
+        } else {
+            let target = self.expr()?;
+            let kind = if self.accept("=") {
+                let value = self.expr()?;
+                match target {
+                    Expr::Var(name) => StmtKind::Assign(name, value),
+                    Expr::Index(name, index) => StmtKind::Store(name, *index, value),
+                    _ => return Err(CompileError { line, message: String::from("can only assign to variables and array elements") }),
+                }
+            } else {
+                StmtKind::Expr(target)
+            };
+            self.expect(";")?;
+            kind
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    // After the `if` keyword; `else if` chains nest.
+    fn if_rest(&mut self) -> Result<StmtKind, CompileError> {
+        self.expect("(")?;
+        let cond = self.expr()?;
+        self.expect(")")?;
+        let then = self.block()?;
+        let line = self.line();
+        let otherwise = if !self.accept_keyword("else") {
+            Vec::new()
+        } else if self.accept_keyword("if") {
+            vec![Stmt { line, kind: self.if_rest()? }]
+        } else {
+            self.block()?
+        };
+        Ok(StmtKind::If(cond, then, otherwise))
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let lhs = self.sum()?;
+        let ops = [("==", BinOp::Eq), ("!=", BinOp::Ne), ("<=", BinOp::Le), (">=", BinOp::Ge),
+                   ("<", BinOp::Lt), (">", BinOp::Gt)];
+        for (punct, op) in ops {
+            if self.accept(punct) {
+                return Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.sum()?)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.accept("+") {
+                BinOp::Add
+            } else if self.accept("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.unary()?;
+        while self.accept("*") {
+            lhs = Expr::Binary(BinOp::Mul, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.accept("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.accept("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        if let Some(&Token::Num(n)) = self.peek() {
+            self.pos += 1;
+            return Ok(Expr::Num(n));
+        }
+        if self.accept("(") {
+            let e = self.expr()?;
+            self.expect(")")?;
+            return Ok(e);
+        }
+        let name = self.ident()?;
+        if self.accept("(") {
+            let mut args = Vec::new();
+            if !self.accept(")") {
+                loop {
+                    args.push(self.expr()?);
+                    if self.accept(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            Ok(Expr::Call(name, args))
+        } else if self.accept("[") {
+            let index = self.expr()?;
+            self.expect("]")?;
+            Ok(Expr::Index(name, Box::new(index)))
+        } else {
+            Ok(Expr::Var(name))
+        }
+    }
+}
+
+const KEYWORDS: [&str; 6] = ["var", "fn", "if", "else", "while", "return"];
+
+////////////////////////////////////////////////////////////////
+
+// Labels for user names get a prefix, so they can't clash with each other
+// or with the compiler's own labels.
+fn function_label(name: &str) -> String {
+    format!("f_{}", name)
+}
+
+fn global_label(name: &str) -> String {
+    format!("g_{}", name)
+}
+
+const RESULT: &str = "_result";
+const STACK: &str = "_stack";
+
+#[derive(Default)]
+struct Generator {
+    out: String,
+    next_label: usize,
+    arities: HashMap<String, usize>,
+    arrays: HashSet<String>,
+    scalars: HashSet<String>,
+    line: usize,
+    // The function being compiled.
+    slots: HashMap<String, usize>,
+    declared: HashSet<String>,
+    frame_size: usize,
+}
+
+fn collect_locals<'s>(stmts: &'s [Stmt], names: &mut Vec<(&'s str, usize)>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Var(name, _) => names.push((name, stmt.line)),
+            StmtKind::If(_, then, otherwise) => {
+                collect_locals(then, names);
+                collect_locals(otherwise, names);
+            },
+            StmtKind::While(_, body) => collect_locals(body, names),
+            _ => (),
+        }
+    }
+}
+
+fn has_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Num(_) | Expr::Var(_) => false,
+        Expr::Call(..) => true,
+        Expr::Index(_, e) | Expr::Neg(e) | Expr::Not(e) => has_call(e),
+        Expr::Binary(_, lhs, rhs) => has_call(lhs) || has_call(rhs),
+    }
+}
+
+fn rel(slot: usize) -> String {
+    format!("rel+{}", slot)
+}
+
+impl Generator {
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError { line: self.line, message })
+    }
+
+    fn emit(&mut self, instr: &str) {
+        self.out.push_str("        ");
+        self.out.push_str(instr);
+        self.out.push('\n');
+    }
+
+    fn label(&mut self) -> String {
+        self.next_label += 1;
+        format!("L{}", self.next_label)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.out.push_str(label);
+        self.out.push_str(":\n");
+    }
+
+    /// Stores the value of `operand` in `dest`, unless it's already there.
+    fn copy(&mut self, operand: &str, dest: &str) {
+        if operand != dest {
+            self.emit(&format!("ADD {} #0 {}", operand, dest));
+        }
+    }
+
+    /// Makes sure the value in `operand` survives evaluating `later`, which
+    /// may call a function that changes a global.
+    fn hold(&mut self, operand: String, depth: usize, later: &Expr) -> String {
+        if operand.starts_with('[') && has_call(later) {
+            self.copy(&operand, &rel(depth));
+            rel(depth)
+        } else {
+            operand
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<String, CompileError> {
+        if let Some(slot) = self.slots.get(name) {
+            if !self.declared.contains(name) {
+                return self.error(format!("variable '{}' used before its declaration", name));
+            }
+            Ok(rel(*slot))
+        } else if self.scalars.contains(name) {
+            Ok(format!("[{}]", global_label(name)))
+        } else if self.arrays.contains(name) {
+            self.error(format!("array '{}' used without an index", name))
+        } else {
+            self.error(format!("undefined variable '{}'", name))
+        }
+    }
+
+    fn array(&self, name: &str) -> Result<String, CompileError> {
+        if self.arrays.contains(name) && !self.slots.contains_key(name) {
+            Ok(global_label(name))
+        } else {
+            self.error(format!("'{}' is not an array", name))
+        }
+    }
+
+    /// Generates code for `expr` and returns an operand holding its value.
+    /// Temporaries go in `depth` and above.
+    fn expr(&mut self, expr: &Expr, depth: usize) -> Result<String, CompileError> {
+        let dest = rel(depth);
+        match expr {
+            Expr::Num(n) => return Ok(format!("#{}", n)),
+            Expr::Var(name) => return self.variable(name),
+            Expr::Index(name, index) => {
+                let base = self.array(name)?;
+                let index = self.expr(index, depth)?;
+                let load = self.label();
+                self.emit(&format!("ADD #{} {} [{}+1]", base, index, load));
+                self.place(&load);
+                self.emit(&format!("ADD [0] #0 {}", dest));
+            },
+            Expr::Call(name, args) => return self.call(name, args, depth),
+            Expr::Neg(e) => {
+                let val = self.expr(e, depth)?;
+                self.emit(&format!("MUL {} #-1 {}", val, dest));
+            },
+            Expr::Not(e) => {
+                let val = self.expr(e, depth)?;
+                self.emit(&format!("EQ {} #0 {}", val, dest));
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let a = self.expr(lhs, depth)?;
+                let a = self.hold(a, depth, rhs);
+                let b = self.expr(rhs, depth + 1)?;
+                let (mnemonic, x, y, negate) = match op {
+                    BinOp::Add => ("ADD", &a, &b, false),
+                    BinOp::Mul => ("MUL", &a, &b, false),
+                    BinOp::Sub => {
+                        let tmp = rel(depth + 1);
+                        self.emit(&format!("MUL {} #-1 {}", b, tmp));
+                        self.emit(&format!("ADD {} {} {}", a, tmp, dest));
+                        return Ok(dest);
+                    },
+                    BinOp::Lt => ("LT", &a, &b, false),
+                    BinOp::Gt => ("LT", &b, &a, false),
+                    BinOp::Ge => ("LT", &a, &b, true),
+                    BinOp::Le => ("LT", &b, &a, true),
+                    BinOp::Eq => ("EQ", &a, &b, false),
+                    BinOp::Ne => ("EQ", &a, &b, true),
+                };
+                self.emit(&format!("{} {} {} {}", mnemonic, x, y, dest));
+                if negate {
+                    self.emit(&format!("EQ {} #0 {}", dest, dest));
+                }
+            },
+        }
+        Ok(dest)
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], depth: usize) -> Result<String, CompileError> {
+        let dest = rel(depth);
+        match (name, args) {
+            ("input", []) => {
+                self.emit(&format!("IN {}", dest));
+                return Ok(dest);
+            },
+            ("output", [arg]) => {
+                let val = self.expr(arg, depth)?;
+                self.emit(&format!("OUT {}", val));
+                return Ok(String::from("#0"));
+            },
+            ("input", _) | ("output", _) => return self.error(format!("wrong number of arguments to '{}'", name)),
+            _ => (),
+        }
+        match self.arities.get(name) {
+            None => return self.error(format!("undefined function '{}'", name)),
+            Some(&arity) if arity != args.len() =>
+                return self.error(format!("'{}' takes {} arguments, got {}", name, arity, args.len())),
+            _ => (),
+        }
+        // The callee's frame starts at `depth`, with the arguments after
+        // the return address.
+        for (ix, arg) in args.iter().enumerate() {
+            let slot = rel(depth + 1 + ix);
+            let val = self.expr(arg, depth + 1 + ix)?;
+            self.copy(&val, &slot);
+        }
+        let ret = self.label();
+        self.emit(&format!("ARB #{}", depth));
+        self.emit(&format!("ADD #{} #0 rel+0", ret));
+        self.emit(&format!("JT #1 #{}", function_label(name)));
+        self.place(&ret);
+        self.emit(&format!("ARB #-{}", depth));
+        self.copy(&format!("[{}]", RESULT), &dest);
+        Ok(dest)
+    }
+
+    fn ret(&mut self, val: &str) {
+        self.copy(val, &format!("[{}]", RESULT));
+        self.emit("JT #1 rel+0");
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        self.line = stmt.line;
+        let temps = self.frame_size;
+        match &stmt.kind {
+            StmtKind::Var(name, init) => {
+                let slot = rel(self.slots[name]);
+                let val = match init {
+                    Some(e) => self.expr(e, temps)?,
+                    None => String::from("#0"),
+                };
+                self.declared.insert(name.clone());
+                self.emit(&format!("ADD {} #0 {}", val, slot));
+            },
+            StmtKind::Assign(name, e) => {
+                let dest = self.variable(name)?;
+                let val = self.expr(e, temps)?;
+                self.copy(&val, &dest);
+            },
+            StmtKind::Store(name, index, e) => {
+                let base = self.array(name)?;
+                let val = self.expr(e, temps)?;
+                let val = self.hold(val, temps, index);
+                let index = self.expr(index, temps + 1)?;
+                let store = self.label();
+                self.emit(&format!("ADD #{} {} [{}+3]", base, index, store));
+                self.place(&store);
+                self.emit(&format!("ADD {} #0 [0]", val));
+            },
+            StmtKind::If(cond, then, otherwise) => {
+                let cond = self.expr(cond, temps)?;
+                let (else_label, end_label) = (self.label(), self.label());
+                self.emit(&format!("JF {} #{}", cond, else_label));
+                self.block(then)?;
+                if !otherwise.is_empty() {
+                    self.emit(&format!("JT #1 #{}", end_label));
+                }
+                self.place(&else_label);
+                self.block(otherwise)?;
+                self.place(&end_label);
+            },
+            StmtKind::While(cond, body) => {
+                let (top, end) = (self.label(), self.label());
+                self.place(&top);
+                let cond = self.expr(cond, temps)?;
+                self.emit(&format!("JF {} #{}", cond, end));
+                self.block(body)?;
+                self.emit(&format!("JT #1 #{}", top));
+                self.place(&end);
+            },
+            StmtKind::Return(value) => {
+                let val = match value {
+                    Some(e) => self.expr(e, temps)?,
+                    None => String::from("#0"),
+                };
+                self.ret(&val);
+            },
+            StmtKind::Expr(e) => {
+                self.expr(e, temps)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.line = function.line;
+        let mut names: Vec<(&str, usize)> = function.params.iter().map(|p| (p.as_str(), function.line)).collect();
+        collect_locals(&function.body, &mut names);
+        self.slots.clear();
+        self.declared.clear();
+        for (ix, (name, line)) in names.iter().enumerate() {
+            if self.slots.insert(name.to_string(), ix + 1).is_some() {
+                return Err(CompileError { line: *line, message: format!("'{}' is declared twice", name) });
+            }
+        }
+        self.declared.extend(function.params.iter().cloned());
+        self.frame_size = names.len() + 1;
+
+        self.out.push('\n');
+        self.place(&function_label(&function.name));
+        self.block(&function.body)?;
+        self.ret("#0");
+        Ok(())
+    }
+
+    fn program(&mut self, program: &Program) -> Result<(), CompileError> {
+        for (line, name, global) in &program.globals {
+            self.line = *line;
+            let fresh = match global {
+                Global::Scalar(_) => self.scalars.insert(name.clone()),
+                Global::Array(_) => self.arrays.insert(name.clone()),
+            };
+            if !fresh || (self.scalars.contains(name) && self.arrays.contains(name)) {
+                return self.error(format!("global '{}' is declared twice", name));
+            }
+        }
+        for function in &program.functions {
+            self.line = function.line;
+            if ["input", "output"].contains(&function.name.as_str()) {
+                return self.error(format!("'{}' is built in", function.name));
+            }
+            if self.arities.insert(function.name.clone(), function.params.len()).is_some() {
+                return self.error(format!("function '{}' is defined twice", function.name));
+            }
+        }
+        match self.arities.get("main") {
+            Some(0) => (),
+            Some(_) => return Err(CompileError { line: 1, message: String::from("'main' can't take arguments") }),
+            None => return Err(CompileError { line: 1, message: String::from("no 'main' function") }),
+        }
+
+        let halt = self.label();
+        self.emit(&format!("ARB #{}", STACK));
+        self.emit(&format!("ADD #{} #0 rel+0", halt));
+        self.emit(&format!("JT #1 #{}", function_label("main")));
+        self.place(&halt);
+        self.emit("HLT");
+        for function in &program.functions {
+            self.function(function)?;
+        }
+
+        self.out.push('\n');
+        self.place(RESULT);
+        self.emit("DATA 0");
+        for (_, name, global) in &program.globals {
+            self.place(&global_label(name));
+            match global {
+                Global::Scalar(val) => self.emit(&format!("DATA {}", val)),
+                Global::Array(size) => self.emit(&format!("DATA {}", vec!["0"; *size].join(", "))),
+            }
+        }
+        self.place(STACK);
+        Ok(())
+    }
+}
+
+/// Compiles a program to assembler source for `asm::assemble`.
+pub fn compile_to_asm(source: &str) -> Result<String, CompileError> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+    let program = parser.program()?;
+    let mut generator = Generator::default();
+    generator.program(&program)?;
+    Ok(generator.out)
+}
+
+pub fn compile(source: &str) -> Result<Vec<Mem>, CompileError> {
+    let asm = compile_to_asm(source)?;
+    assemble(&asm).map_err(|e| CompileError { line: 0, message: format!("internal error in generated code: {}", e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Event, Machine};
+
+    fn run(source: &str, inputs: &[Mem]) -> Vec<Mem> {
+        let mut machine = Machine::new(compile(source).unwrap());
+        for x in inputs {
+            machine.push_input(*x);
+        }
+        let mut out = Vec::new();
+        loop {
+            match machine.resume().unwrap() {
+                Event::Output(x) => out.push(x),
+                Event::Halted => return out,
+                Event::NeedInput => panic!("program wants more input"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "
+            fn fib(n) {
+                if (n < 2) { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            fn pow(base, exp) {
+                var result = 1;
+                while (exp > 0) {
+                    result = result * base;
+                    exp = exp - 1;
+                }
+                return result;
+            }
+
+            fn main() {
+                output(fib(15));
+                output(pow(-3, 3));
+                if (!(pow(2, 10) != 1024)) { output(1); } else if (1) { output(2); }
+                output(3 - 2 - 1 <= 0);
+            }";
+        assert_eq!(run(source, &[]), vec![610, -27, 1, 1]);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = "
+            var data[20];
+            var n;
+
+            // Insertion sort.
+            fn sort() {
+                var i = 1;
+                while (i < n) {
+                    var x = data[i];
+                    var j = i - 1;
+                    var moving = 1;
+                    while (moving) {
+                        if (j < 0) {
+                            moving = 0;
+                        } else if (data[j] <= x) {
+                            moving = 0;
+                        } else {
+                            data[j + 1] = data[j];
+                            j = j - 1;
+                        }
+                    }
+                    data[j + 1] = x;
+                    i = i + 1;
+                }
+            }
+
+            fn main() {
+                n = input();
+                var i = 0;
+                while (i < n) { data[i] = input(); i = i + 1; }
+                sort();
+                i = 0;
+                while (i < n) { output(data[i]); i = i + 1; }
+            }";
+        assert_eq!(run(source, &[6, 5, -1, 9, 3, 3, 0]), vec![-1, 0, 3, 3, 5, 9]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |source: &str| compile(source).unwrap_err();
+        assert_eq!(err("fn main() {\n  x = 1;\n}"),
+                   CompileError { line: 2, message: String::from("undefined variable 'x'") });
+        assert_eq!(err("fn f(a) { return a; }\nfn main() {\n f(1, 2);\n}").line, 3);
+        assert_eq!(err("fn main() { var a[3]; }").message, "arrays must be global");
+        assert_eq!(err("fn f() {}").message, "no 'main' function");
+        assert!(compile("fn main() { output(1) }").is_err());
+    }
+}
@@ -1,6 +1,24 @@
+pub mod ascii;
+pub mod asm;
+pub mod bignum;
+pub mod cached;
+pub mod cfg;
+pub mod circuit;
 pub mod dijkstra;
+pub mod dialect;
 pub mod dir;
+pub mod disasm;
 pub mod grid;
+pub mod history;
 pub mod intcode;
 pub mod io;
+pub mod lang;
+pub mod memsearch;
+pub mod network;
+pub mod optimize;
 pub mod permutation;
+pub mod profile;
+pub mod replay;
+pub mod snapshot;
+pub mod threaded;
+pub mod trace;
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use crate::intcode::{Event, IntcodeError, Machine, Mem, Memory, Ptr};
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Filter {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equals(Mem),
+}
+
+impl Filter {
+    fn matches(&self, old: Mem, new: Mem) -> bool {
+        match *self {
+            Filter::Changed => new != old,
+            Filter::Unchanged => new == old,
+            Filter::Increased => new > old,
+            Filter::Decreased => new < old,
+            Filter::Equals(x) => new == x,
+        }
+    }
+}
+
+/// Narrows down which memory cells hold some piece of state, by comparing
+/// snapshots of memory taken at different times. The cells searched are the
+/// dense part of memory when the search starts: the program image and
+/// whatever the program has written just above it by then. Sparse cells,
+/// and cells the program grows into later, are not searched.
+pub struct MemorySearch {
+    candidates: Vec<(Ptr, Mem)>,
+}
+
+impl MemorySearch {
+    /// Starts with every cell as a candidate.
+    pub fn new(memory: &Memory) -> Self {
+        MemorySearch { candidates: (0..memory.len()).map(|addr| (addr, memory.read(addr))).collect() }
+    }
+
+    /// Keeps the candidates whose value, compared to the previous snapshot,
+    /// matches `filter`, and records the current values as the new snapshot.
+    /// Returns the number of candidates left.
+    pub fn filter(&mut self, memory: &Memory, filter: Filter) -> usize {
+        self.candidates.retain_mut(|(addr, old)| {
+            let new = memory.read(*addr);
+            let keep = filter.matches(*old, new);
+            *old = new;
+            keep
+        });
+        self.candidates.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// The remaining cells with their values in the last snapshot.
+    pub fn candidates(&self) -> &[(Ptr, Mem)] {
+        &self.candidates
+    }
+}
+
+/// Cells held at fixed values while a machine runs.
+#[derive(Default)]
+pub struct Pins {
+    cells: BTreeMap<Ptr, Mem>,
+}
+
+impl Pins {
+    pub fn new() -> Self {
+        Pins::default()
+    }
+
+    pub fn pin(&mut self, addr: Ptr, val: Mem) {
+        self.cells.insert(addr, val);
+    }
+
+    pub fn unpin(&mut self, addr: Ptr) {
+        self.cells.remove(&addr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(Ptr, Mem)> + '_ {
+        self.cells.iter().map(|(&addr, &val)| (addr, val))
+    }
+
+    /// Writes the pinned values into the machine's memory.
+    pub fn apply(&self, machine: &mut Machine) {
+        for (&addr, &val) in &self.cells {
+            if machine.memory().read(addr) != val {
+                machine.poke(addr, val);
+            }
+        }
+    }
+
+    /// Like `Machine::resume`, but restores the pinned values after every
+    /// instruction, so the program never reads anything else from them.
+    pub fn resume(&self, machine: &mut Machine) -> Result<Event, IntcodeError> {
+        if self.cells.is_empty() {
+            return machine.resume();
+        }
+        self.apply(machine);
+        loop {
+            let event = machine.step()?;
+            self.apply(machine);
+            if let Some(event) = event {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // A "game" that awards 10 points per input and ends when lives run out.
+    const GAME: &str = "\
+    top:    IN  [key]
+            ADD [score] #10 [score]
+            ADD [lives] #-1 [lives]
+            OUT [score]
+            JT  [lives] #top
+            HLT
+    key:    DATA 0
+    score:  DATA 0
+    lives:  DATA 3";
+
+    #[test]
+    fn test_search_and_pin() {
+        let program = assemble(GAME).unwrap();
+        let mut machine = Machine::new(program.clone());
+        let mut search = MemorySearch::new(machine.memory());
+        let mut last_output = 0;
+        for key in [7, 7] {
+            machine.push_input(key);
+            if let Event::Output(x) = machine.resume().unwrap() {
+                last_output = x;
+            }
+            search.filter(machine.memory(), Filter::Changed);
+        }
+        // key changed only once, so score and lives remain.
+        assert_eq!(search.candidates(), &[(17, 20), (18, 1)]);
+        search.filter(machine.memory(), Filter::Unchanged);
+        assert_eq!(search.len(), 2);
+
+        let mut search = MemorySearch::new(machine.memory());
+        machine.push_input(7);
+        machine.resume().unwrap();
+        assert_eq!(search.filter(machine.memory(), Filter::Decreased), 1);
+        assert_eq!(search.candidates()[0].0, 18);
+
+        let mut search = MemorySearch::new(machine.memory());
+        search.filter(machine.memory(), Filter::Equals(last_output + 10));
+        assert_eq!(search.candidates(), &[(17, 30)]);
+
+        // With lives pinned, the game never ends.
+        let mut machine = Machine::new(program);
+        let mut pins = Pins::new();
+        pins.pin(18, 3);
+        for _ in 0..10 {
+            machine.push_input(0);
+            assert!(matches!(pins.resume(&mut machine).unwrap(), Event::Output(_)));
+        }
+        assert_eq!(machine.memory().read(17), 100);
+        assert_eq!(machine.memory().read(18), 3);
+
+        // Memory the program has grown into is searched too: IN [10]; HLT
+        let mut machine = Machine::new(vec![3, 10, 99]);
+        machine.push_input(5);
+        machine.resume().unwrap();
+        let mut search = MemorySearch::new(machine.memory());
+        assert_eq!(search.len(), 11);
+        search.filter(machine.memory(), Filter::Equals(5));
+        assert_eq!(search.candidates(), &[(10, 5)]);
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::VecDeque;
+use crate::intcode::{Event, IntcodeError, Machine, Mem};
+
+/// The address the puzzle's NAT listens on.
+pub const NAT_ADDR: Mem = 255;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Packet {
+    pub dest: Mem,
+    pub x: Mem,
+    pub y: Mem,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Route {
+    Node(usize),
+    Nat,
+    Drop,
+}
+
+/// Decides where a packet sent by a node goes.
+pub trait Router {
+    fn route(&mut self, packet: &Packet, nodes: usize) -> Route;
+}
+
+impl<F: FnMut(&Packet, usize) -> Route> Router for F {
+    fn route(&mut self, packet: &Packet, nodes: usize) -> Route {
+        self(packet, nodes)
+    }
+}
+
+/// Delivers packets to the node with the destination address, hands packets
+/// for `nat_addr` to the NAT and drops everything else.
+pub struct DirectRouter {
+    pub nat_addr: Mem,
+}
+
+impl Default for DirectRouter {
+    fn default() -> Self {
+        DirectRouter { nat_addr: NAT_ADDR }
+    }
+}
+
+impl Router for DirectRouter {
+    fn route(&mut self, packet: &Packet, nodes: usize) -> Route {
+        if packet.dest == self.nat_addr {
+            Route::Nat
+        } else if packet.dest >= 0 && (packet.dest as usize) < nodes {
+            Route::Node(packet.dest as usize)
+        } else {
+            Route::Drop
+        }
+    }
+}
+
+pub trait Nat {
+    fn receive(&mut self, packet: Packet);
+
+    /// Called whenever the network is idle. Returns the packet to send, or
+    /// None to stop the simulation.
+    fn wake(&mut self) -> Option<Packet>;
+}
+
+/// A NAT that never sends anything, so the simulation stops at the first
+/// idle network.
+pub struct NoNat;
+
+impl Nat for NoNat {
+    fn receive(&mut self, _packet: Packet) {}
+
+    fn wake(&mut self) -> Option<Packet> {
+        None
+    }
+}
+
+/// The NAT from day 23: on idle, resends the last packet it received to
+/// address 0. It stops once it would send the same y value twice in a row,
+/// or when it has nothing to send.
+#[derive(Default)]
+pub struct RelayNat {
+    first: Option<Packet>,
+    last: Option<Packet>,
+    sent: Vec<Packet>,
+}
+
+impl RelayNat {
+    pub fn new() -> Self {
+        RelayNat { first: None, last: None, sent: Vec::new() }
+    }
+
+    pub fn first_received(&self) -> Option<Packet> {
+        self.first
+    }
+
+    pub fn sent(&self) -> &[Packet] {
+        &self.sent
+    }
+}
+
+impl Nat for RelayNat {
+    fn receive(&mut self, packet: Packet) {
+        self.first.get_or_insert(packet);
+        self.last = Some(packet);
+    }
+
+    fn wake(&mut self) -> Option<Packet> {
+        let packet = Packet { dest: 0, ..self.last? };
+        if self.sent.last().is_some_and(|p| p.y == packet.y) {
+            return None;
+        }
+        self.sent.push(packet);
+        Some(packet)
+    }
+}
+
+/// The order in which nodes get to run.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Schedule {
+    /// Every node handles one event per round, in address order.
+    RoundRobin,
+    /// Every node runs until it polls an empty queue or halts, in address order.
+    UntilBlocked,
+    /// Like `RoundRobin`, but the order is shuffled every round by a
+    /// generator with the given seed.
+    Shuffled(u64),
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Stop {
+    /// The NAT declined to send a packet while the network was idle.
+    NatStopped,
+    AllHalted,
+    RoundLimit,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct LogEntry {
+    pub round: u64,
+    /// The sending node, or None for packets sent by the NAT.
+    pub from: Option<usize>,
+    pub packet: Packet,
+    pub route: Route,
+}
+
+struct Node {
+    machine: Machine,
+    queue: VecDeque<(Mem, Mem)>,
+    partial: Vec<Mem>,
+    empty_polls: usize,
+}
+
+/// A set of intcode machines exchanging (dest, x, y) packets. Node `i` is
+/// started with its address `i` as the first input, and reads -1 whenever its
+/// queue is empty.
+///
+/// The network is idle when no packet is queued or half-sent and every
+/// running node has read -1 at least `idle_polls` times in a row.
+pub struct Network<R: Router = DirectRouter, N: Nat = RelayNat> {
+    nodes: Vec<Node>,
+    router: R,
+    nat: N,
+    schedule: Schedule,
+    idle_polls: usize,
+    max_rounds: Option<u64>,
+    round: u64,
+    rng: u64,
+    log: Option<Vec<LogEntry>>,
+}
+
+impl Network {
+    pub fn new(program: &[Mem], nodes: usize) -> Self {
+        Network::with_parts(program, nodes, DirectRouter::default(), RelayNat::new())
+    }
+}
+
+impl<R: Router, N: Nat> Network<R, N> {
+    pub fn with_parts(program: &[Mem], nodes: usize, router: R, nat: N) -> Self {
+        let nodes = (0..nodes).map(|addr| {
+            let mut machine = Machine::new(program.to_vec());
+            machine.push_input(addr as Mem);
+            Node { machine, queue: VecDeque::new(), partial: Vec::new(), empty_polls: 0 }
+        }).collect();
+        Network {
+            nodes,
+            router,
+            nat,
+            schedule: Schedule::RoundRobin,
+            idle_polls: 2,
+            max_rounds: None,
+            round: 0,
+            rng: 0,
+            log: None,
+        }
+    }
+
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        if let Schedule::Shuffled(seed) = schedule {
+            self.rng = seed | 1;
+        }
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn with_idle_polls(mut self, polls: usize) -> Self {
+        self.idle_polls = polls.max(1);
+        self
+    }
+
+    pub fn with_max_rounds(mut self, rounds: u64) -> Self {
+        self.max_rounds = Some(rounds);
+        self
+    }
+
+    /// Records every routed packet.
+    pub fn with_log(mut self) -> Self {
+        self.log = Some(Vec::new());
+        self
+    }
+
+    pub fn nat(&self) -> &N {
+        &self.nat
+    }
+
+    pub fn router(&self) -> &R {
+        &self.router
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn machine(&self, node: usize) -> &Machine {
+        &self.nodes[node].machine
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn log(&self) -> &[LogEntry] {
+        self.log.as_deref().unwrap_or(&[])
+    }
+
+    /// Queues a packet for a node, as if it had been sent by the NAT.
+    pub fn inject(&mut self, packet: Packet) {
+        let route = self.router.route(&packet, self.nodes.len());
+        self.deliver(None, packet, route);
+    }
+
+    fn deliver(&mut self, from: Option<usize>, packet: Packet, route: Route) {
+        if let Some(log) = self.log.as_mut() {
+            log.push(LogEntry { round: self.round, from, packet, route });
+        }
+        match route {
+            Route::Node(ix) => {
+                self.nodes[ix].queue.push_back((packet.x, packet.y));
+                self.nodes[ix].empty_polls = 0;
+            },
+            Route::Nat => self.nat.receive(packet),
+            Route::Drop => (),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.nodes.iter().all(|node| {
+            node.queue.is_empty() && node.partial.is_empty()
+                && (node.machine.is_halted() || node.empty_polls >= self.idle_polls)
+        })
+    }
+
+    /// Lets node `ix` handle one event. Returns false if it is halted or
+    /// polled an empty queue.
+    fn handle_event(&mut self, ix: usize) -> Result<bool, IntcodeError> {
+        let node = &mut self.nodes[ix];
+        match node.machine.resume()? {
+            Event::NeedInput => match node.queue.pop_front() {
+                Some((x, y)) => {
+                    node.machine.push_input(x);
+                    node.machine.push_input(y);
+                    node.empty_polls = 0;
+                },
+                None => {
+                    node.machine.push_input(-1);
+                    node.empty_polls += 1;
+                    return Ok(false);
+                },
+            },
+            Event::Output(val) => {
+                node.partial.push(val);
+                node.empty_polls = 0;
+                if node.partial.len() == 3 {
+                    let packet = Packet { dest: node.partial[0], x: node.partial[1], y: node.partial[2] };
+                    node.partial.clear();
+                    let route = self.router.route(&packet, self.nodes.len());
+                    self.deliver(Some(ix), packet, route);
+                }
+            },
+            Event::Halted => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn order(&mut self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+        if let Schedule::Shuffled(_) = self.schedule {
+            for i in (1..order.len()).rev() {
+                let j = (self.next_random() % (i as u64 + 1)) as usize;
+                order.swap(i, j);
+            }
+        }
+        order
+    }
+
+    /// Gives every node a turn according to the schedule.
+    pub fn step_round(&mut self) -> Result<(), IntcodeError> {
+        for ix in self.order() {
+            match self.schedule {
+                Schedule::UntilBlocked => while self.handle_event(ix)? {},
+                Schedule::RoundRobin | Schedule::Shuffled(_) => { self.handle_event(ix)?; },
+            }
+        }
+        self.round += 1;
+        Ok(())
+    }
+
+    /// Runs the network, waking the NAT every time it goes idle.
+    pub fn run(&mut self) -> Result<Stop, IntcodeError> {
+        loop {
+            if self.nodes.iter().all(|node| node.machine.is_halted()) {
+                return Ok(Stop::AllHalted);
+            }
+            if self.max_rounds.is_some_and(|max| self.round >= max) {
+                return Ok(Stop::RoundLimit);
+            }
+            if self.is_idle() {
+                match self.nat.wake() {
+                    Some(packet) => self.inject(packet),
+                    None => return Ok(Stop::NatStopped),
+                }
+            }
+            self.step_round()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Node 0 sends (1, 10, 20) once; every node forwards each packet it
+    // receives to the NAT.
+    const FORWARD: &str = "\
+            IN  [addr]
+            JT  [addr] #poll
+            OUT #1
+            OUT #10
+            OUT #20
+    poll:   IN  [x]
+            EQ  [x] #-1 [t]
+            JT  [t] #poll
+            IN  [y]
+            OUT #255
+            OUT [x]
+            OUT [y]
+            JT  #1 #poll
+    addr:   DATA 0
+    x:      DATA 0
+    y:      DATA 0
+    t:      DATA 0";
+
+    #[test]
+    fn test_relay() {
+        let program = assemble(FORWARD).unwrap();
+        for schedule in [Schedule::RoundRobin, Schedule::UntilBlocked, Schedule::Shuffled(42)] {
+            let mut net = Network::new(&program, 3).with_schedule(schedule).with_log();
+            assert_eq!(net.run().unwrap(), Stop::NatStopped);
+            assert_eq!(net.nat().first_received(), Some(Packet { dest: 255, x: 10, y: 20 }));
+            // Node 0 forwards the NAT's packet straight back, so the second
+            // resend would repeat y = 20.
+            assert_eq!(net.nat().sent(), &[Packet { dest: 0, x: 10, y: 20 }]);
+            let log = net.log();
+            assert_eq!(log[0], LogEntry { round: log[0].round, from: Some(0), packet: Packet { dest: 1, x: 10, y: 20 }, route: Route::Node(1) });
+            assert_eq!(log.iter().filter(|e| e.from.is_none()).count(), 1);
+            assert_eq!(log.last().unwrap().route, Route::Nat);
+        }
+    }
+
+    #[test]
+    fn test_router_and_limits() {
+        let program = assemble(FORWARD).unwrap();
+        let mut net = Network::with_parts(&program, 2, |_: &Packet, _| Route::Drop, NoNat).with_log();
+        assert_eq!(net.run().unwrap(), Stop::NatStopped);
+        assert_eq!(net.log().len(), 1);
+        assert_eq!(net.log()[0].route, Route::Drop);
+
+        let mut net = Network::new(&program, 2).with_max_rounds(3);
+        assert_eq!(net.run().unwrap(), Stop::RoundLimit);
+        assert_eq!(net.round(), 3);
+
+        let mut net = Network::new(&[99], 4);
+        assert_eq!(net.run().unwrap(), Stop::AllHalted);
+    }
+}
@@ -0,0 +1,735 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use crate::cfg::{Cfg, Exit};
+use crate::intcode::{decode_instr, Mem, Memory, Op, Param, Ptr};
+
+// The optimizer only rewrites a program once it has shown that no
+// reachable instruction can be overwritten, which takes these steps:
+//
+// 1. Build the control-flow graph. Jumps whose target is read from a cell
+//    are resolved to the cell's initial value; step 4 checks that the cell
+//    is never written.
+// 2. Track the relative base through every function as an offset from its
+//    value at entry. Each call stores the return address at rel+0 and
+//    returns at offset 0, so the return address sits at offset 0 of the
+//    callee's frame. No function, or anything it calls, may write there.
+// 3. Bound the relative base at each function entry from below. Together
+//    with the offsets, that bounds every relative read and write.
+// 4. Collect the cells that may be written, and check that none of them
+//    holds a jump target from step 1 or a part of a reachable instruction
+//    that decides where control or writes go.
+//
+// Instructions are rewritten in place, so no addresses move. Instructions
+// whose own cells may be read or written as data are left alone.
+//
+// In practice that limits it to programs compiled by `lang` and similar
+// hand-written code. Almost all of the puzzle programs keep variables in
+// their instructions, return through written cells or place data where
+// control can reach it, and are refused; of those in `data/`, only day02
+// and day15 are accepted, and only day02 has anything to rewrite.
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Unsafe {
+    /// The block at this address ends in a jump with an unknown target.
+    ComputedJump(Ptr),
+    /// The relative base can't be tracked at this address.
+    RelBase(Ptr),
+    /// The function at this address may overwrite a return address.
+    ReturnSlot(Ptr),
+    /// The instruction at this address may be overwritten.
+    SelfModifying(Ptr),
+    /// The reachable word at this address isn't an instruction.
+    Invalid(Ptr),
+}
+
+impl fmt::Display for Unsafe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unsafe::ComputedJump(addr) => write!(f, "jump with unknown target in block {}", addr),
+            Unsafe::RelBase(addr) => write!(f, "relative base can't be tracked at {}", addr),
+            Unsafe::ReturnSlot(addr) => write!(f, "function {} may overwrite a return address", addr),
+            Unsafe::SelfModifying(addr) => write!(f, "instruction at {} may be overwritten", addr),
+            Unsafe::Invalid(addr) => write!(f, "no valid instruction at {}", addr),
+        }
+    }
+}
+
+impl std::error::Error for Unsafe {}
+
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub struct Stats {
+    /// Position-mode reads of constant cells turned into immediates.
+    pub immediates: usize,
+    /// Arithmetic and comparisons on constants replaced by their result.
+    pub folded: usize,
+    /// Jumps sent straight to the end of a chain of jumps.
+    pub threaded: usize,
+}
+
+fn read_params(op: &Op) -> Vec<Param> {
+    match *op {
+        Op::Add(a, b, _) | Op::Mul(a, b, _) | Op::LessThan(a, b, _) | Op::Equals(a, b, _) |
+        Op::JumpIfTrue(a, b) | Op::JumpIfFalse(a, b) => vec![a, b],
+        Op::Out(a) | Op::AdjustRelBase(a) => vec![a],
+        Op::In(_) | Op::End => vec![],
+    }
+}
+
+fn write_param(op: &Op) -> Option<Param> {
+    match *op {
+        Op::Add(_, _, c) | Op::Mul(_, _, c) | Op::LessThan(_, _, c) | Op::Equals(_, _, c) => Some(c),
+        Op::In(c) => Some(c),
+        _ => None,
+    }
+}
+
+/// The instructions that run exactly once, in this order, before any other
+/// code: the chain of blocks from the entry that can't be reached again.
+fn prologue(cfg: &Cfg) -> Vec<Ptr> {
+    let mut preds: BTreeMap<Ptr, usize> = BTreeMap::new();
+    for block in cfg.blocks.values() {
+        for next in block.exit.successors() {
+            *preds.entry(next).or_insert(0) += 1;
+        }
+    }
+    let mut instrs = Vec::new();
+    let mut at = match cfg.blocks.get(&0) {
+        Some(_) if !preds.contains_key(&0) => 0,
+        _ => return instrs,
+    };
+    loop {
+        let block = &cfg.blocks[&at];
+        instrs.extend(&block.instrs);
+        match block.exit {
+            Exit::Fallthrough(next) | Exit::Jump(next) if preds[&next] == 1 => at = next,
+            _ => return instrs,
+        }
+    }
+}
+
+fn local_successors(exit: &Exit) -> Vec<Ptr> {
+    match *exit {
+        Exit::Call { ret, .. } => vec![ret],
+        _ => exit.successors(),
+    }
+}
+
+/// Iterates `update` until it reports no change. Fails if that takes more
+/// than `rounds` rounds, which means some cycle keeps lowering a bound.
+fn fixpoint<F: FnMut() -> Result<bool, Unsafe>>(rounds: usize, mut update: F) -> Result<(), Unsafe> {
+    for _ in 0..=rounds {
+        if !update()? {
+            return Ok(());
+        }
+    }
+    Err(Unsafe::RelBase(0))
+}
+
+struct Analysis {
+    /// The reachable instructions, as they are in the program.
+    ops: BTreeMap<Ptr, Op>,
+    /// Cells that belong to more than one reachable instruction.
+    shared: BTreeSet<Ptr>,
+    /// Cells whose initial value is assumed when resolving jumps.
+    assumed: BTreeSet<Ptr>,
+    /// Position-mode write targets; everything at or above `rel_write` may
+    /// also be written.
+    writes: BTreeSet<Ptr>,
+    rel_write: Mem,
+    reads: BTreeSet<Ptr>,
+    rel_read: Mem,
+}
+
+impl Analysis {
+    fn written(&self, addr: Ptr) -> bool {
+        self.writes.contains(&addr) || addr as Mem >= self.rel_write
+    }
+
+    fn read(&self, addr: Ptr) -> bool {
+        self.reads.contains(&addr) || addr as Mem >= self.rel_read
+    }
+}
+
+fn analyse(program: &[Mem]) -> Result<Analysis, Unsafe> {
+    let memory = Memory::new(program.to_vec());
+
+    // Step 1.
+    let mut image = program.to_vec();
+    let mut assumed = BTreeSet::new();
+    let mut resolved = BTreeSet::new();
+    let cfg = loop {
+        let cfg = Cfg::build(&image);
+        let mut more = false;
+        for start in cfg.computed_jumps() {
+            let addr = *cfg.blocks[&start].instrs.last().unwrap();
+            let cell = match decode_instr(&memory, addr) {
+                Ok(Op::JumpIfTrue(_, Param::Pos(cell))) | Ok(Op::JumpIfFalse(_, Param::Pos(cell))) => cell,
+                _ => return Err(Unsafe::ComputedJump(start)),
+            };
+            match program.get(cell) {
+                Some(&target) if target >= 0 && addr + 2 < image.len() && !resolved.contains(&addr) => {
+                    image[addr + 2] = target;
+                    image[addr] += 1000;
+                    assumed.insert(cell);
+                    resolved.insert(addr);
+                    more = true;
+                },
+                _ => return Err(Unsafe::ComputedJump(start)),
+            }
+        }
+        if !more {
+            break cfg;
+        }
+    };
+
+    // The cells of every reachable instruction. A resolved jump only reads
+    // like that in the image, so no other instruction may overlap it.
+    let mut owners: BTreeMap<Ptr, usize> = BTreeMap::new();
+    let mut ops = BTreeMap::new();
+    for block in cfg.blocks.values() {
+        let mut end = block.start;
+        for &addr in &block.instrs {
+            let op = decode_instr(&memory, addr).map_err(|_| Unsafe::Invalid(addr))?;
+            end = addr + op.len();
+            for cell in addr..end {
+                *owners.entry(cell).or_insert(0) += 1;
+            }
+            ops.insert(addr, op);
+        }
+        if block.exit == Exit::Invalid {
+            return Err(Unsafe::Invalid(end));
+        }
+    }
+    let shared: BTreeSet<Ptr> = owners.into_iter().filter(|&(_, n)| n > 1).map(|(cell, _)| cell).collect();
+    if let Some(&addr) = resolved.iter().find(|&&addr| (addr..addr + 3).any(|cell| shared.contains(&cell))) {
+        return Err(Unsafe::ComputedJump(addr));
+    }
+
+    // Step 2: the relative base offset at each instruction of each function.
+    let entries: Vec<Ptr> = cfg.functions.iter().map(|f| f.entry).collect();
+    let mut offsets: Vec<BTreeMap<Ptr, Mem>> = Vec::new();
+    let mut calls = Vec::new();
+    for (fx, function) in cfg.functions.iter().enumerate() {
+        let mut at_block = BTreeMap::from([(function.entry, 0)]);
+        let mut at_instr = BTreeMap::new();
+        let mut work = vec![function.entry];
+        while let Some(start) = work.pop() {
+            let block = cfg.blocks.get(&start).ok_or(Unsafe::Invalid(start))?;
+            let mut offset: Mem = at_block[&start];
+            for &addr in &block.instrs {
+                at_instr.insert(addr, offset);
+                match ops[&addr] {
+                    Op::AdjustRelBase(Param::Imm(adj)) => offset = offset.checked_add(adj).ok_or(Unsafe::RelBase(addr))?,
+                    Op::AdjustRelBase(_) => return Err(Unsafe::RelBase(addr)),
+                    _ => (),
+                }
+            }
+            match block.exit {
+                Exit::Call { target, .. } => {
+                    let callee = entries.iter().position(|&e| e == target).unwrap();
+                    calls.push((fx, offset, callee));
+                },
+                Exit::Return if fx == 0 || offset != 0 => return Err(Unsafe::RelBase(*block.instrs.last().unwrap())),
+                _ => (),
+            }
+            for next in local_successors(&block.exit) {
+                match at_block.insert(next, offset) {
+                    None => work.push(next),
+                    Some(seen) if seen != offset => return Err(Unsafe::RelBase(next)),
+                    Some(_) => (),
+                }
+            }
+        }
+        offsets.push(at_instr);
+    }
+
+    // Relative accesses of each function, as (offset + adjustment, write).
+    let mut accesses: Vec<Vec<(Mem, bool)>> = vec![Vec::new(); entries.len()];
+    for (fx, at_instr) in offsets.iter().enumerate() {
+        for (&addr, &offset) in at_instr {
+            let op = &ops[&addr];
+            let rel = read_params(op).into_iter().map(|p| (p, false)).chain(write_param(op).map(|p| (p, true)));
+            for (p, write) in rel {
+                if let Param::Rel(adj) = p {
+                    accesses[fx].push((offset.checked_add(adj).ok_or(Unsafe::RelBase(addr))?, write));
+                }
+            }
+        }
+    }
+
+    // Step 3: the lowest relative base at each function entry.
+    let n = entries.len();
+    let mut entry_base: Vec<Option<Mem>> = vec![None; n];
+    entry_base[0] = Some(0);
+    fixpoint(n, || {
+        let mut changed = false;
+        for &(caller, offset, callee) in &calls {
+            if let Some(base) = entry_base[caller] {
+                let base = base.checked_add(offset).ok_or(Unsafe::RelBase(entries[callee]))?;
+                if entry_base[callee].is_none_or(|b| base < b) {
+                    entry_base[callee] = Some(base);
+                    changed = true;
+                }
+            }
+        }
+        Ok(changed)
+    })?;
+
+    // The lowest frame offset each function writes to, including through
+    // the functions it calls.
+    let mut lowest_write: Vec<Mem> = accesses.iter()
+        .map(|a| a.iter().filter(|(_, w)| *w).map(|(at, _)| *at).min().unwrap_or(Mem::MAX))
+        .collect();
+    fixpoint(n, || {
+        let mut changed = false;
+        for &(caller, offset, callee) in &calls {
+            if lowest_write[callee] == Mem::MAX {
+                continue;
+            }
+            let through = lowest_write[callee].checked_add(offset).ok_or(Unsafe::RelBase(entries[caller]))?;
+            if through < lowest_write[caller] {
+                lowest_write[caller] = through;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    })?;
+    if let Some(fx) = (1..n).find(|&fx| lowest_write[fx] < 1) {
+        return Err(Unsafe::ReturnSlot(entries[fx]));
+    }
+
+    // Step 4.
+    let (mut rel_write, mut rel_read) = (Mem::MAX, Mem::MAX);
+    for (fx, function_accesses) in accesses.iter().enumerate() {
+        let base = entry_base[fx].ok_or(Unsafe::RelBase(entries[fx]))?;
+        for &(at, write) in function_accesses {
+            let lowest = if write { &mut rel_write } else { &mut rel_read };
+            *lowest = std::cmp::min(*lowest, base.checked_add(at).ok_or(Unsafe::RelBase(entries[fx]))?);
+        }
+    }
+    let mut analysis = Analysis { ops, shared, assumed, writes: BTreeSet::new(), rel_write, reads: BTreeSet::new(), rel_read };
+    let lowest_slot = (1..n).filter_map(|fx| entry_base[fx]).min().unwrap_or(Mem::MAX);
+    for (&addr, op) in &analysis.ops {
+        for p in read_params(op) {
+            if let Param::Pos(cell) = p {
+                analysis.reads.insert(cell);
+            }
+        }
+        if let Some(Param::Pos(cell)) = write_param(op) {
+            if cell as Mem >= lowest_slot {
+                return Err(Unsafe::ReturnSlot(addr));
+            }
+            analysis.writes.insert(cell);
+        }
+    }
+    // Reachable code may still be written in a few harmless ways: code that
+    // runs once at startup before anything writes to it, immediates used as
+    // data, and addresses of reads, which then may read anything.
+    let prologue = prologue(&cfg);
+    let runs_once = |addr: Ptr, cells: &std::ops::Range<Ptr>| match prologue.iter().position(|&a| a == addr) {
+        Some(_) if cells.end as Mem > analysis.rel_write => false,
+        Some(ix) => prologue[..ix].iter().all(|a| match write_param(&analysis.ops[a]) {
+            Some(Param::Pos(cell)) => !cells.contains(&cell),
+            Some(Param::Rel(_)) => false,
+            _ => true,
+        }),
+        None => false,
+    };
+    let mut reads_anything = false;
+    for (&addr, &op) in &analysis.ops {
+        let cells = addr..addr + op.len();
+        if runs_once(addr, &cells) {
+            continue;
+        }
+        let jump = matches!(op, Op::JumpIfTrue(..) | Op::JumpIfFalse(..));
+        let call = matches!(write_param(&op), Some(Param::Rel(0)));
+        let data = match op {
+            Op::AdjustRelBase(_) => 0,
+            _ if jump => 1,
+            _ => read_params(&op).len(),
+        };
+        for cell in cells.filter(|&cell| analysis.written(cell)) {
+            let ix = cell - addr;
+            match op.params().get(ix.wrapping_sub(1)) {
+                Some(Param::Imm(_)) if ix <= data && !jump && !call => (),
+                Some(Param::Pos(_)) if ix <= data => reads_anything = true,
+                _ => return Err(Unsafe::SelfModifying(addr)),
+            }
+        }
+    }
+    if reads_anything {
+        analysis.rel_read = 0;
+    }
+    if let Some(&cell) = analysis.assumed.iter().find(|&&cell| analysis.written(cell)) {
+        return Err(Unsafe::SelfModifying(cell));
+    }
+    Ok(analysis)
+}
+
+fn encode(op: &Op) -> Vec<Mem> {
+    let mut words = vec![op.opcode()];
+    for p in op.params() {
+        words.push(match *p {
+            Param::Pos(ptr) => ptr as Mem,
+            Param::Imm(val) | Param::Rel(val) => val,
+        });
+    }
+    words
+}
+
+struct Rewriter<'a> {
+    program: &'a [Mem],
+    analysis: &'a Analysis,
+    stats: Stats,
+}
+
+impl Rewriter<'_> {
+    /// The parameter with a constant cell read as an immediate.
+    fn constant(&mut self, p: Param) -> Param {
+        match p {
+            Param::Pos(cell) if cell < self.program.len() && !self.analysis.written(cell) => {
+                self.stats.immediates += 1;
+                Param::Imm(self.program[cell])
+            },
+            _ => p,
+        }
+    }
+
+    /// The instruction with its constant reads made immediate, and folded
+    /// if that leaves nothing to compute.
+    fn simplify(&mut self, op: Op) -> Op {
+        let op = match op {
+            Op::Add(a, b, c) => Op::Add(self.constant(a), self.constant(b), c),
+            Op::Mul(a, b, c) => Op::Mul(self.constant(a), self.constant(b), c),
+            Op::LessThan(a, b, c) => Op::LessThan(self.constant(a), self.constant(b), c),
+            Op::Equals(a, b, c) => Op::Equals(self.constant(a), self.constant(b), c),
+            Op::JumpIfTrue(a, b) => Op::JumpIfTrue(self.constant(a), self.constant(b)),
+            Op::JumpIfFalse(a, b) => Op::JumpIfFalse(self.constant(a), self.constant(b)),
+            Op::Out(a) => Op::Out(self.constant(a)),
+            _ => op,
+        };
+        let folded = match op {
+            Op::Add(Param::Imm(0), Param::Imm(_), _) | Op::Add(Param::Imm(_), Param::Imm(0), _) => None,
+            Op::Add(Param::Imm(a), Param::Imm(b), c) => a.checked_add(b).map(|v| (v, c)),
+            Op::Mul(Param::Imm(a), Param::Imm(b), c) => a.checked_mul(b).map(|v| (v, c)),
+            Op::LessThan(Param::Imm(a), Param::Imm(b), c) => Some(((a < b) as Mem, c)),
+            Op::Equals(Param::Imm(a), Param::Imm(b), c) => Some(((a == b) as Mem, c)),
+            _ => None,
+        };
+        match folded {
+            Some((val, dest)) => {
+                self.stats.folded += 1;
+                Op::Add(Param::Imm(val), Param::Imm(0), dest)
+            },
+            None => op,
+        }
+    }
+
+    /// Where the instruction at `addr` always jumps to, if it's a reachable
+    /// unconditional jump with a known target that is never written.
+    fn always_jumps(&self, addr: Ptr) -> Option<Ptr> {
+        let op = self.analysis.ops.get(&addr)?;
+        if (addr..addr + op.len()).any(|cell| self.analysis.written(cell)) {
+            return None;
+        }
+        let known = |p: Param| match p {
+            Param::Imm(v) => Some(v),
+            Param::Pos(cell) if cell < self.program.len() && !self.analysis.written(cell) => Some(self.program[cell]),
+            _ => None,
+        };
+        let (cond, target, if_true) = match *op {
+            Op::JumpIfTrue(c, t) => (c, t, true),
+            Op::JumpIfFalse(c, t) => (c, t, false),
+            _ => return None,
+        };
+        match (known(cond), known(target)) {
+            (Some(c), Some(t)) if (c != 0) == if_true && t >= 0 => Some(t as Ptr),
+            _ => None,
+        }
+    }
+
+    fn thread(&mut self, target: Param) -> Param {
+        let mut t = match target {
+            Param::Imm(t) if t >= 0 => t as Ptr,
+            _ => return target,
+        };
+        let mut seen = BTreeSet::from([t]);
+        while let Some(next) = self.always_jumps(t) {
+            if !seen.insert(next) {
+                break;
+            }
+            t = next;
+        }
+        if target != Param::Imm(t as Mem) {
+            self.stats.threaded += 1;
+        }
+        Param::Imm(t as Mem)
+    }
+
+    fn rewrite(&mut self, op: Op) -> Op {
+        match self.simplify(op) {
+            Op::JumpIfTrue(c, t) => Op::JumpIfTrue(c, self.thread(t)),
+            Op::JumpIfFalse(c, t) => Op::JumpIfFalse(c, self.thread(t)),
+            op => op,
+        }
+    }
+}
+
+/// Rewrites a program into one with the same behaviour, with constant
+/// reads, arithmetic and jump chains simplified. Programs that can't be
+/// shown to be free of self-modification are refused.
+pub fn optimize(program: &[Mem]) -> Result<(Vec<Mem>, Stats), Unsafe> {
+    let analysis = analyse(program)?;
+    let mut rewriter = Rewriter { program, analysis: &analysis, stats: Stats::default() };
+    let mut out = program.to_vec();
+    for (&addr, &op) in &analysis.ops {
+        // Instructions that overlap another one are left alone, since
+        // rewriting either would change the other.
+        let cells = addr..addr + op.len();
+        if cells.end > program.len() || cells.clone().any(|cell| {
+            analysis.shared.contains(&cell) || analysis.read(cell) || analysis.written(cell)
+        }) {
+            continue;
+        }
+        out[cells].copy_from_slice(&encode(&rewriter.rewrite(op)));
+    }
+    Ok((out, rewriter.stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::intcode::{Budget, Event, Machine};
+    use crate::lang::compile;
+
+    #[test]
+    fn test_rewrites() {
+        let program = assemble("\
+                OUT [k]
+                MUL [k] #3 [x]
+                JT  [k] #hop
+                HLT
+        hop:    JT  #1 #end
+        end:    OUT [x]
+                HLT
+        k:      DATA 7
+        x:      DATA 0").unwrap();
+        let (optimized, stats) = optimize(&program).unwrap();
+        assert_eq!(optimized, assemble("\
+                OUT #7
+                ADD #21 #0 [x]
+                JT  #7 #end
+                HLT
+        hop:    JT  #1 #end
+        end:    OUT [x]
+                HLT
+        k:      DATA 7
+        x:      DATA 0").unwrap());
+        assert_eq!(stats, Stats { immediates: 3, folded: 1, threaded: 1 });
+
+        // Writing to k makes every read of it non-constant, and writing to
+        // code makes the program unsafe to touch.
+        let writes_k = assemble("IN [k]\nOUT [k]\nHLT\nk: DATA 7").unwrap();
+        assert_eq!(optimize(&writes_k).unwrap(), (writes_k.clone(), Stats::default()));
+        let writes_code = assemble("IN [top]\ntop: OUT #0\nHLT").unwrap();
+        assert_eq!(optimize(&writes_code), Err(Unsafe::SelfModifying(2)));
+    }
+
+    type Input = Box<dyn FnMut(usize) -> Mem>;
+
+    /// Runs both programs on the same inputs and checks that they produce
+    /// the same events, up to `events` of them. Threaded jumps save steps,
+    /// so only the original has to finish within `steps` for each event.
+    /// Once both halt, every cell the optimizer didn't rewrite must match.
+    fn differential(original: &[Mem], optimized: &[Mem], mut input: impl FnMut(usize) -> Mem, events: usize, steps: u64) {
+        let mut a = Machine::new(original.to_vec());
+        let mut b = Machine::new(optimized.to_vec());
+        let budget = Budget::steps(steps);
+        let mut inputs = 0;
+        for _ in 0..events {
+            let ea = a.resume_bounded(budget);
+            if ea == Ok(None) {
+                break;
+            }
+            match (ea.clone(), b.resume_bounded(budget)) {
+                // Rewritten instructions fault with another opcode.
+                (Err(ea), Err(eb)) => assert_eq!((ea.kind, ea.ip), (eb.kind, eb.ip), "{:?}", original),
+                (ea, eb) => assert_eq!(ea, eb, "{:?}", original),
+            }
+            match ea {
+                Ok(Some(Event::NeedInput)) => {
+                    let x = input(inputs);
+                    inputs += 1;
+                    a.push_input(x);
+                    b.push_input(x);
+                },
+                Ok(Some(Event::Output(_))) => (),
+                Ok(Some(Event::Halted)) => {
+                    let (ma, mb) = (a.memory(), b.memory());
+                    assert_eq!(ma.len(), mb.len(), "{:?}", original);
+                    for addr in (0..ma.len()).filter(|&addr| original.get(addr) == optimized.get(addr)) {
+                        assert_eq!(ma.read(addr), mb.read(addr), "cell {} of {:?}", addr, original);
+                    }
+                    break;
+                },
+                _ => break,
+            }
+        }
+    }
+
+    #[test]
+    fn test_overlap() {
+        // The branch at 2 lands on the second word of the ADD at 5, which
+        // reads as OUT [21]. Folding the ADD would break the OUT.
+        let mut program = vec![3, 20, 1005, 20, 6, 101, 4, 21, 99, 4, 99, 99];
+        program.resize(20, 0);
+        program.extend(&[0, 7]);
+        let (optimized, _) = optimize(&program).unwrap();
+        assert_eq!(optimized, program);
+        differential(&program, &optimized, |_| 0, 10, 100);
+        differential(&program, &optimized, |_| 1, 10, 100);
+
+        // Jumps into the middle of instructions, and a resolved jump that
+        // overlaps another instruction.
+        let program = vec![205, 4, 7, 99, 4, 7, 2206, 1, -1, 99, 105, 2, 18, 1001, 9, 0, 6];
+        let optimized = optimize(&program).map_or(program.clone(), |(optimized, _)| optimized);
+        let mut machine = Machine::new(optimized);
+        assert_eq!(machine.resume(), Ok(Event::Output(1)));
+
+        // Unusual programs are refused rather than panicking.
+        assert!(optimize(&[3, 16, 22008, 5, 0, -3, 206, -2, 15, 1201, 4, 7, 2, 20202, 1, 8, 4]).is_err());
+        assert!(optimize(&[1206, 3, 13, 102, 8, 12, 9, 104, 2, 9, 12, 1201, 0, 5, 1]).is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        // A call through a function pointer in a constant cell, with the
+        // argument and result passed in the callee's frame.
+        let program = assemble("\
+                ARB #stack
+                IN  [n]
+                ADD [n] #0 rel+1
+                ADD #back #0 rel+0
+                JT  #1 [fptr]
+        back:   OUT rel+1
+                MUL [k] [k] [x]
+                LT  [n] #10 [flag]
+                JT  [flag] #hop
+                HLT
+        hop:    JT  #1 #hop2
+        hop2:   JF  #0 #end
+        end:    OUT [x]
+                HLT
+        square: ARB #2
+                MUL rel-1 rel-1 rel-1
+                ADD rel-1 [k] rel-1
+                ARB #-2
+                JT  #1 rel+0
+        fptr:   DATA square
+        k:      DATA 7
+        n:      DATA 0
+        x:      DATA 0
+        flag:   DATA 0
+        stack:  DATA 0").unwrap();
+        let (optimized, stats) = optimize(&program).unwrap();
+        assert_eq!(stats, Stats { immediates: 4, folded: 1, threaded: 2 });
+        for n in [3, 12] {
+            differential(&program, &optimized, |_| n, 10, 1000);
+        }
+        let mut machine = Machine::new(optimized);
+        machine.push_input(3);
+        assert_eq!(machine.resume(), Ok(Event::Output(16)));
+        assert_eq!(machine.resume(), Ok(Event::Output(49)));
+
+        // A callee that writes over its return address is refused.
+        let clobbers = assemble("ADD #back #0 rel+0\nJT #1 #f\nback: HLT\nf: ADD #0 #0 rel+0\nJT #1 rel+0").unwrap();
+        assert_eq!(optimize(&clobbers), Err(Unsafe::ReturnSlot(8)));
+    }
+
+    /// A short program of random instructions and small operands, which
+    /// finds odd corners like jumps into the middle of instructions.
+    fn random_program(rng: &mut u64) -> Vec<Mem> {
+        let mut next = || {
+            // xorshift64
+            *rng ^= *rng << 13;
+            *rng ^= *rng >> 7;
+            *rng ^= *rng << 17;
+            *rng
+        };
+        let len = 8 + next() % 16;
+        (0..len).map(|_| {
+            let r = next();
+            if r % 2 == 0 {
+                let opcode = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99][(r / 2 % 10) as usize];
+                (opcode + r / 20 % 3 * 100 + r / 60 % 3 * 1000 + r / 180 % 3 * 10000) as Mem
+            } else {
+                (r / 2 % (len + 6)) as Mem - 3
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_random_programs() {
+        let mut rng = 0x2545f4914f6cdd1d;
+        let mut changed = 0;
+        for _ in 0..20000 {
+            let program = random_program(&mut rng);
+            if let Ok((optimized, _)) = optimize(&program) {
+                changed += (optimized != program) as usize;
+                differential(&program, &optimized, |ix| ix as Mem % 3, 20, 1000);
+            }
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn test_differential() {
+        let load = |source: &str| crate::io::parse_intcode_program(&source.to_string());
+        let ascii = |text: &'static str| move |ix: usize| text.as_bytes().get(ix).map_or(10, |&c| c as Mem);
+        let walk = "NOT A J\nNOT B T\nOR T J\nNOT C T\nOR T J\nAND D J\nWALK\n";
+        let mut day02 = load(include_str!("../data/day02.in"));
+        day02[1] = 12;
+        day02[2] = 2;
+        let programs: Vec<(&str, Vec<Mem>, Input)> = vec![
+            ("day02", day02, Box::new(|_| 0)),
+            ("day05", load(include_str!("../data/day05.in")), Box::new(|_| 5)),
+            ("day07", load(include_str!("../data/day07.in")), Box::new(|ix| [3, 0, 1, 0][ix % 4])),
+            ("day09", load(include_str!("../data/day09.in")), Box::new(|_| 1)),
+            ("day11", load(include_str!("../data/day11.in")), Box::new(|ix| (ix % 3 == 0) as Mem)),
+            ("day13", load(include_str!("../data/day13.in")), Box::new(|ix| (ix % 3) as Mem - 1)),
+            ("day15", load(include_str!("../data/day15.in")), Box::new(|ix| (ix * 7 % 4) as Mem + 1)),
+            ("day17", load(include_str!("../data/day17.in")), Box::new(|_| 10)),
+            ("day19", load(include_str!("../data/day19.in")), Box::new(|ix| (ix * 13 % 50) as Mem)),
+            ("day21", load(include_str!("../data/day21.in")), Box::new(ascii(walk))),
+            ("day23", load(include_str!("../data/day23.in")), Box::new(|ix| if ix == 0 { 3 } else { -1 })),
+            ("day25", load(include_str!("../data/day25.in")), Box::new(ascii("north\ntake mug\ninv\n"))),
+            ("pow", compile("
+                fn pow(base, exp) {
+                    var result = 1;
+                    while (exp > 0) { result = result * base; exp = exp - 1; }
+                    return result;
+                }
+                fn main() {
+                    var n = input();
+                    output(pow(n, 3 - 1) + pow(2, 10 - 3 * 2));
+                    if (1 < 2) { output(n); }
+                }").unwrap(), Box::new(|_| 7)),
+        ];
+        // Most of the puzzle programs modify themselves, jump through cells
+        // they write, or keep data in their code, and are refused.
+        let mut accepted = vec![];
+        let mut changed = vec![];
+        for (name, program, input) in programs {
+            if let Ok((optimized, _)) = optimize(&program) {
+                accepted.push(name);
+                if optimized != program {
+                    changed.push(name);
+                }
+                differential(&program, &optimized, input, 5000, 5_000_000);
+            }
+        }
+        assert_eq!(accepted, vec!["day02", "day15", "pow"]);
+        assert_eq!(changed, vec!["day02", "pow"]);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::disasm::{format_op, mnemonic};
+use crate::intcode::{Mem, Op, Param, Ptr, TraceStep, Tracer};
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum SortBy {
+    /// Most frequent first.
+    Count,
+    Address,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct InstrStats {
+    pub addr: Ptr,
+    pub count: u64,
+    /// The instruction last executed at this address.
+    pub op: Op,
+}
+
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub struct CellStats {
+    pub addr: Ptr,
+    pub reads: u64,
+    pub writes: u64,
+    /// Writes made by `IN`.
+    pub inputs: u64,
+    /// Reads made by `OUT`.
+    pub outputs: u64,
+}
+
+impl CellStats {
+    fn total(&self) -> u64 {
+        self.reads + self.writes
+    }
+}
+
+/// A backward jump with an immediate target, taken `iterations` times.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Loop {
+    pub from: Ptr,
+    pub to: Ptr,
+    pub iterations: u64,
+}
+
+/// A tracer that counts what a program does: executions per address and
+/// opcode, reads and writes per memory cell, and taken backward jumps.
+/// Only jumps to an immediate target count as loops; computed jumps back,
+/// like function returns, are left out.
+/// Counts accumulate over all runs it is used for.
+#[derive(Default)]
+pub struct Profiler {
+    steps: u64,
+    instrs: HashMap<Ptr, (u64, Op)>,
+    opcodes: HashMap<&'static str, u64>,
+    cells: HashMap<Ptr, CellStats>,
+    loops: HashMap<(Ptr, Ptr), u64>,
+}
+
+fn param_addr(param: &Param, rel_base: Mem) -> Option<Ptr> {
+    match *param {
+        Param::Pos(ptr) => Some(ptr),
+        Param::Imm(_) => None,
+        Param::Rel(adj) => rel_base.checked_add(adj).filter(|&addr| addr >= 0).map(|addr| addr as Ptr),
+    }
+}
+
+fn sort_by<T, K: Ord>(items: &mut [T], sort: SortBy, count: impl Fn(&T) -> u64, addr: impl Fn(&T) -> K) {
+    match sort {
+        SortBy::Count => items.sort_by(|a, b| count(b).cmp(&count(a)).then_with(|| addr(a).cmp(&addr(b)))),
+        SortBy::Address => items.sort_by_key(addr),
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Number of instructions executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn exec_count(&self, addr: Ptr) -> u64 {
+        self.instrs.get(&addr).map_or(0, |(count, _)| *count)
+    }
+
+    pub fn cell(&self, addr: Ptr) -> CellStats {
+        self.cells.get(&addr).cloned().unwrap_or(CellStats { addr, ..CellStats::default() })
+    }
+
+    pub fn instructions(&self, sort: SortBy) -> Vec<InstrStats> {
+        let mut v: Vec<InstrStats> = self.instrs.iter()
+            .map(|(&addr, &(count, op))| InstrStats { addr, count, op })
+            .collect();
+        sort_by(&mut v, sort, |s| s.count, |s| s.addr);
+        v
+    }
+
+    /// Executions per mnemonic. `SortBy::Address` sorts by name.
+    pub fn opcodes(&self, sort: SortBy) -> Vec<(&'static str, u64)> {
+        let mut v: Vec<(&'static str, u64)> = self.opcodes.iter().map(|(&k, &n)| (k, n)).collect();
+        sort_by(&mut v, sort, |e| e.1, |e| e.0);
+        v
+    }
+
+    /// Count order is by reads and writes together.
+    pub fn cells(&self, sort: SortBy) -> Vec<CellStats> {
+        let mut v: Vec<CellStats> = self.cells.values().cloned().collect();
+        sort_by(&mut v, sort, CellStats::total, |c| c.addr);
+        v
+    }
+
+    /// The cells that `IN` writes to or `OUT` reads from.
+    pub fn io_cells(&self) -> Vec<CellStats> {
+        let mut v: Vec<CellStats> = self.cells.values().filter(|c| c.inputs + c.outputs > 0).cloned().collect();
+        v.sort_by_key(|c| c.addr);
+        v
+    }
+
+    /// Address order is by the jump target, i.e. the loop head.
+    pub fn loops(&self, sort: SortBy) -> Vec<Loop> {
+        let mut v: Vec<Loop> = self.loops.iter()
+            .map(|(&(from, to), &iterations)| Loop { from, to, iterations })
+            .collect();
+        sort_by(&mut v, sort, |l| l.iterations, |l| (l.to, l.from));
+        v
+    }
+
+    /// A readable summary; each table is cut off after `limit` rows.
+    pub fn report(&self, sort: SortBy, limit: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} instructions executed", self.steps).unwrap();
+
+        writeln!(out, "\nopcodes:").unwrap();
+        for (name, count) in self.opcodes(sort).into_iter().take(limit) {
+            writeln!(out, "  {:<4} {:>12}", name, count).unwrap();
+        }
+
+        writeln!(out, "\ninstructions:").unwrap();
+        for s in self.instructions(sort).into_iter().take(limit) {
+            writeln!(out, "  {:>5}: {:>12}  {}", s.addr, s.count, format_op(&s.op)).unwrap();
+        }
+
+        writeln!(out, "\nloops:").unwrap();
+        for l in self.loops(sort).into_iter().take(limit) {
+            writeln!(out, "  {:>5} -> {:<5} {:>12} iterations, {} words", l.from, l.to, l.iterations, l.from - l.to).unwrap();
+        }
+
+        writeln!(out, "\ncells:              reads       writes").unwrap();
+        for c in self.cells(sort).into_iter().take(limit) {
+            writeln!(out, "  [{:>5}]  {:>12} {:>12}", c.addr, c.reads, c.writes).unwrap();
+        }
+
+        writeln!(out, "\nI/O cells:          inputs      outputs").unwrap();
+        for c in self.io_cells().into_iter().take(limit) {
+            writeln!(out, "  [{:>5}]  {:>12} {:>12}", c.addr, c.inputs, c.outputs).unwrap();
+        }
+        out
+    }
+
+    fn cell_mut(&mut self, addr: Ptr) -> &mut CellStats {
+        self.cells.entry(addr).or_insert(CellStats { addr, ..CellStats::default() })
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, step: &TraceStep) {
+        self.steps += 1;
+        let instr = self.instrs.entry(step.ip).or_insert((0, *step.op));
+        instr.0 += 1;
+        instr.1 = *step.op;
+        *self.opcodes.entry(mnemonic(step.op)).or_insert(0) += 1;
+
+        // The operands are the parameters that were read, so a jump that
+        // isn't taken doesn't count its target.
+        let is_out = matches!(step.op, Op::Out(_));
+        for param in step.op.params().into_iter().take(step.operands.len()) {
+            if let Some(addr) = param_addr(param, step.rel_base) {
+                let cell = self.cell_mut(addr);
+                cell.reads += 1;
+                if is_out {
+                    cell.outputs += 1;
+                }
+            }
+        }
+        if let Some(w) = step.write {
+            let is_in = matches!(step.op, Op::In(_));
+            let cell = self.cell_mut(w.addr);
+            cell.writes += 1;
+            if is_in {
+                cell.inputs += 1;
+            }
+        }
+
+        let direct = matches!(step.op, Op::JumpIfTrue(_, Param::Imm(_)) | Op::JumpIfFalse(_, Param::Imm(_)));
+        if direct && step.new_ip <= step.ip {
+            *self.loops.entry((step.ip, step.new_ip)).or_insert(0) += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::intcode::Machine;
+
+    #[test]
+    fn test_profile() {
+        // Sums the numbers from n down to 1.
+        let program = assemble("\
+                IN  [n]
+        top:    ADD [sum] [n] [sum]
+                ADD [n] #-1 [n]
+                JT  [n] #top
+                OUT [sum]
+                HLT
+        n:      DATA 0
+        sum:    DATA 0").unwrap();
+        let n_addr = 16;
+        let sum_addr = 17;
+        let mut profiler = Profiler::new();
+        let mut machine = Machine::new(program);
+        machine.push_input(10);
+        assert_eq!(machine.resume_traced(&mut profiler).unwrap(), crate::intcode::Event::Output(55));
+
+        assert_eq!(profiler.exec_count(2), 10);
+        assert_eq!(profiler.exec_count(15), 0);
+        assert_eq!(profiler.steps(), 1 + 3 * 10 + 1);
+        assert_eq!(profiler.opcodes(SortBy::Count)[0], ("ADD", 20));
+        assert_eq!(profiler.loops(SortBy::Count), vec![Loop { from: 10, to: 2, iterations: 9 }]);
+
+        let n = profiler.cell(n_addr);
+        assert_eq!((n.reads, n.writes, n.inputs, n.outputs), (30, 11, 1, 0));
+        let sum = profiler.cell(sum_addr);
+        assert_eq!((sum.reads, sum.writes, sum.inputs, sum.outputs), (11, 10, 0, 1));
+        let io: Vec<Ptr> = profiler.io_cells().iter().map(|c| c.addr).collect();
+        assert_eq!(io, vec![n_addr, sum_addr]);
+
+        let addrs: Vec<Ptr> = profiler.instructions(SortBy::Address).iter().map(|s| s.addr).collect();
+        assert_eq!(addrs, vec![0, 2, 6, 10, 13]);
+        let report = profiler.report(SortBy::Count, 3);
+        assert!(report.contains("    2:           10  ADD  [17] [16] [17]"));
+
+        // A jump only reads its target when it is taken.
+        let program = assemble("\
+                JT  #0 [t]
+                JT  #1 [t]
+                HLT
+        end:    HLT
+        t:      DATA end").unwrap();
+        let mut profiler = Profiler::new();
+        Machine::new(program).resume_traced(&mut profiler).unwrap();
+        assert_eq!(profiler.cell(8).reads, 1);
+
+        // Jumping back through a cell, like a return does, isn't a loop.
+        let program = assemble("\
+        top:    ADD [n] #-1 [n]
+                JF  [n] #end
+                JT  #1 [t]
+        end:    HLT
+        n:      DATA 3
+        t:      DATA top").unwrap();
+        let mut profiler = Profiler::new();
+        Machine::new(program).resume_traced(&mut profiler).unwrap();
+        assert_eq!(profiler.exec_count(0), 3);
+        assert_eq!(profiler.loops(SortBy::Count), vec![]);
+    }
+}
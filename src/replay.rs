@@ -0,0 +1,276 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::intcode::{Event, IntcodeError, Machine, Mem, Op, TraceStep, Tracer};
+
+// Log file layout: a header line, then one line per entry with the index of
+// the instruction (counting from 0) that consumed or produced the value:
+//
+//     # intcode io log v1
+//     12 in 5
+//     40 out 7
+//     97 halt
+
+const HEADER: &str = "# intcode io log v1";
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Entry {
+    Input { step: u64, value: Mem },
+    Output { step: u64, value: Mem },
+    Halt { step: u64 },
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Entry::Input { step, value } => write!(f, "{} in {}", step, value),
+            Entry::Output { step, value } => write!(f, "{} out {}", step, value),
+            Entry::Halt { step } => write!(f, "{} halt", step),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogError::Io(e) => write!(f, "{}", e),
+            LogError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl From<io::Error> for LogError {
+    fn from(e: io::Error) -> Self {
+        LogError::Io(e)
+    }
+}
+
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct Log {
+    pub entries: Vec<Entry>,
+}
+
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Result<Entry, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let num = |s: &str| s.parse::<Mem>().map_err(|_| format!("invalid number '{}'", s));
+    let step = |s: &str| s.parse::<u64>().map_err(|_| format!("invalid step '{}'", s));
+    match words[..] {
+        [s, "in", v] => Ok(Entry::Input { step: step(s)?, value: num(v)? }),
+        [s, "out", v] => Ok(Entry::Output { step: step(s)?, value: num(v)? }),
+        [s, "halt"] => Ok(Entry::Halt { step: step(s)? }),
+        _ => Err(format!("invalid entry '{}'", line)),
+    }
+}
+
+impl Log {
+    pub fn parse(text: &str) -> Result<Log, LogError> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, l)| l.trim()) != Some(HEADER) {
+            return Err(LogError::Parse { line: 1, message: String::from("missing header") });
+        }
+        let mut entries = Vec::new();
+        for (ix, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(parse_entry(line).map_err(|message| LogError::Parse { line: ix + 1, message })?);
+        }
+        Ok(Log { entries })
+    }
+
+    /// The recorded inputs, in order.
+    pub fn inputs(&self) -> impl Iterator<Item=Mem> + '_ {
+        self.entries.iter().filter_map(|e| match e {
+            Entry::Input { value, .. } => Some(*value),
+            _ => None,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LogError> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Log, LogError> {
+        Log::parse(&fs::read_to_string(path)?)
+    }
+}
+
+fn entry_for(step: &TraceStep, index: u64) -> Option<Entry> {
+    match step.op {
+        Op::In(_) => step.write.map(|w| Entry::Input { step: index, value: w.new }),
+        Op::Out(_) => Some(Entry::Output { step: index, value: step.operands[0] }),
+        Op::End => Some(Entry::Halt { step: index }),
+        _ => None,
+    }
+}
+
+/// A tracer that logs every input consumed and output produced. Use the same
+/// recorder for the whole run, so that step indices count from its start.
+#[derive(Default)]
+pub struct Recorder {
+    steps: u64,
+    log: Log,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    pub fn log(&self) -> &Log {
+        &self.log
+    }
+
+    pub fn into_log(self) -> Log {
+        self.log
+    }
+}
+
+impl Tracer for Recorder {
+    fn trace(&mut self, step: &TraceStep) {
+        if let Some(entry) = entry_for(step, self.steps) {
+            self.log.entries.push(entry);
+        }
+        self.steps += 1;
+    }
+}
+
+#[derive(Debug,PartialEq,Eq)]
+pub enum ReplayError {
+    Intcode(IntcodeError),
+    /// Entry `index` of the log didn't match. `actual` is None if the
+    /// program asked for input the log doesn't have; `expected` is None if
+    /// the program went on past the end of the log.
+    Diverged { index: usize, expected: Option<Entry>, actual: Option<Entry> },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |e: &Option<Entry>| e.map_or(String::from("nothing"), |e| e.to_string());
+        match self {
+            ReplayError::Intcode(e) => write!(f, "{}", e),
+            ReplayError::Diverged { index, expected, actual } =>
+                write!(f, "replay diverged at entry {}: expected {}, got {}", index, show(expected), show(actual)),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+struct Verifier<'a> {
+    log: &'a Log,
+    steps: u64,
+    matched: usize,
+    divergence: Option<ReplayError>,
+}
+
+impl Tracer for Verifier<'_> {
+    fn trace(&mut self, step: &TraceStep) {
+        if let Some(actual) = entry_for(step, self.steps) {
+            let expected = self.log.entries.get(self.matched).cloned();
+            if expected == Some(actual) {
+                self.matched += 1;
+            } else if self.divergence.is_none() {
+                self.divergence = Some(ReplayError::Diverged { index: self.matched, expected, actual: Some(actual) });
+            }
+        }
+        self.steps += 1;
+    }
+}
+
+/// Runs `program` from the start on the inputs in `log`, checking that every
+/// input, output and the halt happen at the same step as recorded. Stops at
+/// the first difference. A log that ends before the program halts is fine
+/// as long as the program then waits for more input.
+pub fn replay(program: Vec<Mem>, log: &Log) -> Result<(), ReplayError> {
+    let mut machine = Machine::new(program);
+    for x in log.inputs() {
+        machine.push_input(x);
+    }
+    let mut verifier = Verifier { log, steps: 0, matched: 0, divergence: None };
+    loop {
+        let event = machine.step_traced(&mut verifier).map_err(ReplayError::Intcode)?;
+        if let Some(divergence) = verifier.divergence.take() {
+            return Err(divergence);
+        }
+        match event {
+            Some(Event::NeedInput) | Some(Event::Halted) => break,
+            Some(Event::Output(_)) | None => (),
+        }
+    }
+    match log.entries.get(verifier.matched) {
+        None => Ok(()),
+        Some(expected) => Err(ReplayError::Diverged { index: verifier.matched, expected: Some(*expected), actual: None }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IN [9]; MUL [9] #3 [10]; OUT [10]; HLT
+    const TRIPLE: [Mem; 11] = [3, 9, 1002, 9, 3, 10, 4, 10, 99, 0, 0];
+
+    fn record(program: Vec<Mem>, inputs: &[Mem]) -> Log {
+        let mut machine = Machine::new(program);
+        let mut recorder = Recorder::new();
+        for x in inputs {
+            machine.push_input(*x);
+        }
+        while let Event::Output(_) = machine.resume_traced(&mut recorder).unwrap() {}
+        recorder.into_log()
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let log = record(TRIPLE.to_vec(), &[5]);
+        assert_eq!(log.entries, vec![
+            Entry::Input { step: 0, value: 5 },
+            Entry::Output { step: 2, value: 15 },
+            Entry::Halt { step: 3 },
+        ]);
+        let text = log.to_string();
+        assert_eq!(text, "# intcode io log v1\n0 in 5\n2 out 15\n3 halt\n");
+        assert_eq!(Log::parse(&text).unwrap(), log);
+        assert_eq!(replay(TRIPLE.to_vec(), &log), Ok(()));
+    }
+
+    #[test]
+    fn test_divergence() {
+        let mut log = record(TRIPLE.to_vec(), &[5]);
+        // A program that doubles instead of tripling.
+        let mut double = TRIPLE.to_vec();
+        double[4] = 2;
+        assert_eq!(replay(double, &log), Err(ReplayError::Diverged {
+            index: 1,
+            expected: Some(Entry::Output { step: 2, value: 15 }),
+            actual: Some(Entry::Output { step: 2, value: 10 }),
+        }));
+
+        // Without the input, the program stops waiting for it.
+        log.entries.remove(0);
+        assert!(matches!(replay(TRIPLE.to_vec(), &log),
+                         Err(ReplayError::Diverged { index: 0, actual: None, .. })));
+
+        assert!(matches!(Log::parse("# intcode io log v1\n3 jump 4\n"), Err(LogError::Parse { line: 2, .. })));
+    }
+}
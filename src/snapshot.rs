@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use crate::intcode::{Arithmetic, Machine, Mem, Memory, Ptr};
+
+// Snapshot file layout: the magic bytes, then a sequence of LEB128 varints
+// (signed values zigzag-encoded):
+//
+//     version, ip, rel_base, halted, arithmetic (0 wrapping, 1 checked),
+//     input count, inputs...,
+//     dense length, dense cells...,
+//     far count, (address, value)...
+
+const MAGIC: &[u8; 4] = b"ICSS";
+pub const VERSION: u64 = 2;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u64),
+    Truncated,
+    Overlong,
+    UnknownArithmetic(u64),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::BadMagic => write!(f, "not an intcode snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Overlong => write!(f, "snapshot contains an overlong number"),
+            SnapshotError::UnknownArithmetic(a) => write!(f, "unknown arithmetic policy {}", a),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+fn put_unsigned(buf: &mut Vec<u8>, mut x: u64) {
+    loop {
+        let byte = (x & 0x7f) as u8;
+        x >>= 7;
+        if x == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn put_signed(buf: &mut Vec<u8>, x: Mem) {
+    put_unsigned(buf, ((x << 1) ^ (x >> 63)) as u64)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn unsigned(&mut self) -> Result<u64, SnapshotError> {
+        let mut x = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.pos).ok_or(SnapshotError::Truncated)?;
+            self.pos += 1;
+            x |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(SnapshotError::Overlong)
+    }
+
+    fn signed(&mut self) -> Result<Mem, SnapshotError> {
+        let x = self.unsigned()?;
+        Ok((x >> 1) as Mem ^ -((x & 1) as Mem))
+    }
+
+    fn count(&mut self) -> Result<usize, SnapshotError> {
+        let n = self.unsigned()? as usize;
+        // Every entry takes at least one byte, so a larger count can't be genuine.
+        if n > self.data.len() - self.pos {
+            return Err(SnapshotError::Truncated);
+        }
+        Ok(n)
+    }
+}
+
+pub fn write_snapshot<W: Write>(machine: &Machine, out: &mut W) -> io::Result<()> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    put_unsigned(&mut buf, VERSION);
+    put_unsigned(&mut buf, machine.ip() as u64);
+    put_signed(&mut buf, machine.rel_base());
+    put_unsigned(&mut buf, machine.is_halted() as u64);
+    put_unsigned(&mut buf, match machine.arithmetic() {
+        Arithmetic::Wrapping => 0,
+        Arithmetic::Checked => 1,
+    });
+
+    let input = machine.input_queue();
+    put_unsigned(&mut buf, input.len() as u64);
+    for x in input {
+        put_signed(&mut buf, *x);
+    }
+
+    put_unsigned(&mut buf, machine.memory().len() as u64);
+    for x in machine.memory().dense() {
+        put_signed(&mut buf, x);
+    }
+
+    let far = machine.memory().far_cells();
+    put_unsigned(&mut buf, far.len() as u64);
+    for (addr, x) in far {
+        put_unsigned(&mut buf, addr as u64);
+        put_signed(&mut buf, x);
+    }
+
+    out.write_all(&buf)
+}
+
+pub fn read_snapshot<R: Read>(input: &mut R) -> Result<Machine, SnapshotError> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let mut r = Reader { data: &data, pos: MAGIC.len() };
+
+    let version = r.unsigned()?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let ip = r.unsigned()? as Ptr;
+    let rel_base = r.signed()?;
+    let halted = r.unsigned()? != 0;
+    let arithmetic = match r.unsigned()? {
+        0 => Arithmetic::Wrapping,
+        1 => Arithmetic::Checked,
+        a => return Err(SnapshotError::UnknownArithmetic(a)),
+    };
+
+    let mut queue = VecDeque::new();
+    for _ in 0..r.count()? {
+        queue.push_back(r.signed()?);
+    }
+
+    let mut dense = Vec::new();
+    for _ in 0..r.count()? {
+        dense.push(r.signed()?);
+    }
+    let mut memory = Memory::new(dense);
+    for _ in 0..r.count()? {
+        let addr = r.unsigned()? as Ptr;
+        memory.write(addr, r.signed()?);
+    }
+
+    Ok(Machine::from_parts(memory, ip, rel_base, queue, halted).with_arithmetic(arithmetic))
+}
+
+pub fn save(machine: &Machine, path: &Path) -> Result<(), SnapshotError> {
+    let mut file = File::create(path)?;
+    write_snapshot(machine, &mut file)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Machine, SnapshotError> {
+    read_snapshot(&mut File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Event;
+
+    fn snapshot_bytes(machine: &Machine) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_snapshot(machine, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // ARB #-5; IN [13]; OUT [13]; ADD #1 #1 [10^12]; IN [14]; HLT
+        let program = vec![109, -5, 3, 13, 4, 13, 1101, 1, 1, 1_000_000_000_000, 3, 14, 99];
+        let mut machine = Machine::new(program);
+        machine.push_input(-77);
+        machine.push_input(12);
+        machine.push_input(i64::MIN);
+        assert_eq!(machine.resume().unwrap(), Event::Output(-77));
+        machine.step().unwrap();
+
+        let bytes = snapshot_bytes(&machine);
+        let mut restored = read_snapshot(&mut &bytes[..]).unwrap();
+        assert_eq!(snapshot_bytes(&restored), bytes);
+        assert_eq!(restored.ip(), machine.ip());
+        assert_eq!(restored.rel_base(), -5);
+        assert_eq!(restored.pending_input(), 2);
+        assert_eq!(restored.memory().read(1_000_000_000_000), 2);
+
+        assert_eq!(restored.resume().unwrap(), Event::Halted);
+        assert_eq!(restored.memory().read(14), 12);
+
+        // The arithmetic policy survives, so overflow is still reported.
+        // ADD #MAX #1 [5]
+        let machine = Machine::new(vec![1101, i64::MAX, 1, 5, 99]).with_arithmetic(Arithmetic::Checked);
+        let mut restored = read_snapshot(&mut &snapshot_bytes(&machine)[..]).unwrap();
+        assert_eq!(restored.arithmetic(), Arithmetic::Checked);
+        assert!(restored.resume().is_err());
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert!(matches!(read_snapshot(&mut &b"nope"[..]), Err(SnapshotError::BadMagic)));
+        assert!(matches!(read_snapshot(&mut &b"ICSS\x01"[..]), Err(SnapshotError::UnsupportedVersion(1))));
+
+        let bytes = snapshot_bytes(&Machine::new(vec![1, 2, 3]));
+        let truncated = &bytes[..bytes.len() - 2];
+        assert!(matches!(read_snapshot(&mut &truncated[..]), Err(SnapshotError::Truncated)));
+    }
+}
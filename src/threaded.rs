@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use crate::intcode::{
+    continue_program_bounded, Budget, ErrorKind, Input, InputOutput, IntcodeError,
+    Machine, Mem, Output, RunOutcome,
+};
+
+/// How often a blocked input checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How many instructions a running machine executes between shutdown checks.
+const SHUTDOWN_CHECK_STEPS: u64 = 100_000;
+
+/// Reads input from a channel. While waiting for a value, the `blocked` flag
+/// is raised; a closed channel reports `InputExhausted`.
+pub struct ChannelInput {
+    rx: Receiver<Mem>,
+    blocked: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ChannelInput {
+    pub fn new(rx: Receiver<Mem>) -> Self {
+        ChannelInput {
+            rx,
+            blocked: Arc::new(AtomicBool::new(false)),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn blocked_flag(&self) -> Arc<AtomicBool> {
+        self.blocked.clone()
+    }
+
+    /// Setting the returned flag makes a blocked read fail.
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+}
+
+impl Input for ChannelInput {
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+        if let Ok(x) = self.rx.try_recv() {
+            return Ok(x);
+        }
+        self.blocked.store(true, Ordering::SeqCst);
+        let result = loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                break Err(ErrorKind::InputFailed(String::from("machine shut down")));
+            }
+            match self.rx.recv_timeout(POLL_INTERVAL) {
+                Ok(x) => break Ok(x),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break Err(ErrorKind::InputExhausted),
+            }
+        };
+        self.blocked.store(false, Ordering::SeqCst);
+        result
+    }
+}
+
+/// Sends output to a channel. Values sent after the receiver has gone away
+/// are dropped.
+pub struct ChannelOutput {
+    tx: Sender<Mem>,
+}
+
+impl ChannelOutput {
+    pub fn new(tx: Sender<Mem>) -> Self {
+        ChannelOutput { tx }
+    }
+}
+
+impl Output for ChannelOutput {
+    fn next_output(&mut self, x: Mem) {
+        let _ = self.tx.send(x);
+    }
+}
+
+struct ChannelIO {
+    /// Input that was already queued on the machine, read before the channel.
+    queued: VecDeque<Mem>,
+    input: ChannelInput,
+    output: ChannelOutput,
+}
+
+impl InputOutput for ChannelIO {
+    fn next_input(&mut self) -> Result<Mem, ErrorKind> {
+        match self.queued.pop_front() {
+            Some(x) => Ok(x),
+            None => self.input.next_input(),
+        }
+    }
+
+    fn next_output(&mut self, x: Mem) {
+        self.output.next_output(x)
+    }
+}
+
+#[derive(Debug,PartialEq,Eq)]
+pub enum Exit {
+    Halted(Vec<Mem>),
+    InputClosed,
+    Shutdown,
+}
+
+pub struct MachineHandle {
+    blocked: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Exit, IntcodeError>>,
+}
+
+impl MachineHandle {
+    /// True while the machine waits for input that hasn't arrived yet.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked.load(Ordering::SeqCst)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    pub fn join(self) -> Result<Exit, IntcodeError> {
+        self.thread.join().expect("intcode thread panicked")
+    }
+
+    /// Stops the machine, whether it is running or blocked, and waits for it.
+    pub fn shutdown(self) -> Result<Exit, IntcodeError> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.join()
+    }
+}
+
+fn run(mut machine: Machine, mut io: ChannelIO, shutdown: Arc<AtomicBool>) -> Result<Exit, IntcodeError> {
+    loop {
+        match continue_program_bounded(machine, &mut io, Budget::steps(SHUTDOWN_CHECK_STEPS)) {
+            Ok(RunOutcome::Halted(memory)) => return Ok(Exit::Halted(memory)),
+            Ok(RunOutcome::BudgetExhausted(m)) => {
+                if shutdown.load(Ordering::SeqCst) {
+                    return Ok(Exit::Shutdown);
+                }
+                machine = m;
+            },
+            Err(_) if shutdown.load(Ordering::SeqCst) => return Ok(Exit::Shutdown),
+            Err(e) if e.kind == ErrorKind::InputExhausted => return Ok(Exit::InputClosed),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs `machine` on its own thread, reading input from `input` and sending
+/// output to `output`. Input already queued on the machine is read first.
+pub fn spawn(machine: Machine, input: Receiver<Mem>, output: Sender<Mem>) -> MachineHandle {
+    let input = ChannelInput::new(input);
+    let blocked = input.blocked_flag();
+    let shutdown = input.shutdown_flag();
+    let queued = machine.input_queue().clone();
+    let io = ChannelIO { queued, input, output: ChannelOutput::new(output) };
+    let thread_shutdown = shutdown.clone();
+    let thread = std::thread::spawn(move || run(machine, io, thread_shutdown));
+    MachineHandle { blocked, shutdown, thread }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_feedback_loop() {
+        // The second feedback example from day 7.
+        let program = vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+        let phases = [9, 8, 7, 6, 5];
+
+        let (txs, rxs): (Vec<Sender<Mem>>, Vec<Receiver<Mem>>) = phases.iter().map(|_| channel()).unzip();
+        let (last_tx, last_rx) = channel();
+        let mut handles = Vec::new();
+        for (ix, rx) in rxs.into_iter().enumerate() {
+            txs[ix].send(phases[ix]).unwrap();
+            let tx = txs.get(ix + 1).cloned().unwrap_or_else(|| last_tx.clone());
+            handles.push(spawn(Machine::new(program.clone()), rx, tx));
+        }
+        drop(last_tx);
+
+        // Feed the last amplifier's output back to the first one.
+        txs[0].send(0).unwrap();
+        let mut last = None;
+        for x in last_rx.iter() {
+            last = Some(x);
+            let _ = txs[0].send(x);
+        }
+        assert_eq!(last, Some(139629729));
+        for handle in handles {
+            assert!(matches!(handle.join(), Ok(Exit::Halted(_))));
+        }
+    }
+
+    #[test]
+    fn test_blocked_and_shutdown() {
+        // IN [5]; JT #1 #0
+        let (tx, rx) = channel();
+        let (out_tx, _out_rx) = channel();
+        let handle = spawn(Machine::new(vec![3, 5, 1105, 1, 0, 0]), rx, out_tx);
+        tx.send(1).unwrap();
+        while !handle.is_blocked() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(handle.shutdown(), Ok(Exit::Shutdown));
+
+        // A busy loop must stop as well: JT #1 #0
+        let (_tx, rx) = channel();
+        let (out_tx, _out_rx) = channel();
+        let handle = spawn(Machine::new(vec![1105, 1, 0]), rx, out_tx);
+        assert_eq!(handle.shutdown(), Ok(Exit::Shutdown));
+    }
+
+    #[test]
+    fn test_input_closed() {
+        let (tx, rx) = channel();
+        let (out_tx, out_rx) = channel();
+        // IN [7]; OUT [7]; JT #1 #0
+        let handle = spawn(Machine::new(vec![3, 7, 4, 7, 1105, 1, 0, 0]), rx, out_tx);
+        tx.send(4).unwrap();
+        tx.send(5).unwrap();
+        drop(tx);
+        assert_eq!(handle.join(), Ok(Exit::InputClosed));
+        assert_eq!(out_rx.iter().collect::<Vec<Mem>>(), vec![4, 5]);
+
+        // Input queued before spawning comes before the channel's.
+        let (tx, rx) = channel();
+        let (out_tx, out_rx) = channel();
+        let mut machine = Machine::new(vec![3, 7, 4, 7, 1105, 1, 0, 0]);
+        machine.push_input(3);
+        let handle = spawn(machine, rx, out_tx);
+        tx.send(4).unwrap();
+        drop(tx);
+        assert_eq!(handle.join(), Ok(Exit::InputClosed));
+        assert_eq!(out_rx.iter().collect::<Vec<Mem>>(), vec![3, 4]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use crate::disasm::format_op;
+use crate::intcode::{Mem, MemWrite, Op, Ptr, TraceStep, Tracer};
+
+/// An owned copy of a `TraceStep`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Record {
+    pub ip: Ptr,
+    pub rel_base: Mem,
+    pub op: Op,
+    pub operands: Vec<Mem>,
+    pub write: Option<MemWrite>,
+    pub new_ip: Ptr,
+    pub new_rel_base: Mem,
+}
+
+impl From<&TraceStep<'_>> for Record {
+    fn from(step: &TraceStep) -> Self {
+        Record {
+            ip: step.ip,
+            rel_base: step.rel_base,
+            op: *step.op,
+            operands: step.operands.to_vec(),
+            write: step.write,
+            new_ip: step.new_ip,
+            new_rel_base: step.new_rel_base,
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operands: Vec<String> = self.operands.iter().map(|x| x.to_string()).collect();
+        write!(f, "{:>5}: {:<28} args {:<16}", self.ip, format_op(&self.op), operands.join(","))?;
+        match self.write {
+            Some(w) => write!(f, " [{}] {} -> {:<8}", w.addr, w.old, w.new)?,
+            None => write!(f, " {:<16}", "")?,
+        }
+        write!(f, " ip={} rb={}", self.new_ip, self.new_rel_base)
+    }
+}
+
+/// Writes one line per executed instruction. The first write error stops
+/// tracing and is reported by `finish`.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        TraceWriter { out, error: None }
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.out.flush().map(|_| self.out),
+        }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", Record::from(step)) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Keeps the last `capacity` executed instructions.
+pub struct TraceRing {
+    capacity: usize,
+    records: VecDeque<Record>,
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        TraceRing { capacity, records: VecDeque::with_capacity(capacity) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Oldest record first.
+    pub fn records(&self) -> impl Iterator<Item=&Record> {
+        self.records.iter()
+    }
+
+    pub fn last(&self) -> Option<&Record> {
+        self.records.back()
+    }
+}
+
+impl Tracer for TraceRing {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(Record::from(step));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{run_program_traced, Machine, Param};
+
+    struct NoIO;
+
+    impl crate::intcode::InputOutput for NoIO {
+        fn next_input(&mut self) -> Result<Mem, crate::intcode::ErrorKind> {
+            Ok(5)
+        }
+
+        fn next_output(&mut self, _x: Mem) {}
+    }
+
+    #[test]
+    fn test_ring() {
+        // IN [9]; MUL [9] #3 [10]; OUT [10]; HLT
+        let program = vec![3, 9, 1002, 9, 3, 10, 4, 10, 99, 0, 0];
+        let mut ring = TraceRing::new(3);
+        run_program_traced(program, &mut NoIO, &mut ring).unwrap();
+        assert_eq!(ring.len(), 3);
+        let records: Vec<&Record> = ring.records().collect();
+        assert_eq!(records[0].op, Op::Mul(Param::Pos(9), Param::Imm(3), Param::Pos(10)));
+        assert_eq!(records[0].operands, vec![5, 3]);
+        assert_eq!(records[0].write, Some(MemWrite { addr: 10, old: 0, new: 15 }));
+        assert_eq!(records[0].new_ip, 6);
+        assert_eq!(records[1].operands, vec![15]);
+        assert_eq!(records[2].op, Op::End);
+        assert_eq!(records[2].new_ip, 8);
+    }
+
+    #[test]
+    fn test_writer() {
+        // ARB #7; OUT rel+0; HLT
+        let mut machine = Machine::new(vec![109, 7, 204, 0, 99]);
+        let mut writer = TraceWriter::new(Vec::new());
+        machine.resume_traced(&mut writer).unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("    0: ARB  #7"));
+        assert!(lines[0].ends_with("ip=2 rb=7"));
+        assert!(lines[1].starts_with("    2: OUT  rel+0"));
+    }
+}